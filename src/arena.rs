@@ -0,0 +1,477 @@
+//! Procedurally generated arenas, as an alternative to the levels made in LDtk. An arena is the
+//! classic layout: a border wall with a grid of pillars inside it, and the rest filled with bombable
+//! tiles at random, apart from an L-shaped safe zone in each corner where the players spawn.
+//!
+//! The arena is generated when [`GENERATED_LEVEL`] is selected, using the [`ArenaSettings`] from the
+//! ruleset and a seed from the [`GameRng`]. The seed is kept as the [`ArenaSeed`], so that the same
+//! arena can be generated again elsewhere, such as when a snapshot is loaded or by the clients of a
+//! dedicated server, by inserting a [`NextArenaSeed`]. It is spawned with the same structure as an
+//! LDtk level, with tiles on `Ground`, `Maze` and `Bombable` layers and `Player_N` spawn points, so
+//! the rest of the gameplay doesn't need to know where the level came from.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::Type, prelude::*};
+use bevy_ecs_tilemap::{
+    map::{TilemapId, TilemapSize, TilemapTexture, TilemapTileSize, TilemapType},
+    tiles::{TileBundle, TilePos, TileStorage, TileTextureIndex},
+    TilemapBundle,
+};
+use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::{
+    ldtk::{TileSize, ToWorld},
+    netplay::MAX_PLAYERS,
+    rules::Ruleset,
+    GameRng, GameState,
+};
+
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::LoadingLevel),
+            spawn_arena.run_if(generated_level_selected),
+        )
+        .add_systems(OnExit(GameState::InGame), despawn_arena);
+    }
+}
+
+/// The level identifier which selects a generated arena instead of a level from `level.ldtk`.
+pub const GENERATED_LEVEL: &str = "Generated_Arena";
+
+/// The smallest arena with room for the pillars and the safe zones.
+const MIN_SIZE: i32 = 7;
+
+/// The tileset and tile ID used for each layer, matching the tiles used in `level.ldtk`.
+const GROUND_TILE: (&str, u32) = ("tiles/Ground Wood.png", 18);
+const MAZE_TILE: (&str, u32) = ("tiles/Ground Metal.png", 18);
+const BOMBABLE_TILE: (&str, u32) = ("tiles/default_tileset.png", 44);
+
+/// Settings for generated arenas, under the `[ruleset.arena]` table of the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArenaSettings {
+    /// The size of the arena in tiles, including the border. Both must be odd, so that the pillars
+    /// line up with the border.
+    pub width: i32,
+    pub height: i32,
+    /// The chance of each free tile being bombable, from 0 to 1.
+    pub density: f32,
+    pub symmetry: Symmetry,
+}
+
+impl Default for ArenaSettings {
+    fn default() -> Self {
+        Self {
+            width: 15,
+            height: 11,
+            density: 0.7,
+            symmetry: Symmetry::Quad,
+        }
+    }
+}
+
+/// How the bombable tiles of a generated arena are mirrored, so that no spawn point is luckier than
+/// another.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Symmetry {
+    None,
+    /// Mirrored left to right.
+    Horizontal,
+    /// Mirrored top to bottom.
+    Vertical,
+    /// Mirrored both ways, so every corner is the same.
+    #[default]
+    Quad,
+}
+
+/// The tiles of a generated arena, before it is spawned.
+#[derive(Debug, Clone)]
+pub struct ArenaLayout {
+    pub width: i32,
+    pub height: i32,
+    pub walls: Vec<GridCoords>,
+    pub bombable: Vec<GridCoords>,
+    /// The spawn point of each player, in order.
+    pub spawns: [GridCoords; MAX_PLAYERS],
+}
+
+impl ArenaSettings {
+    /// The width and height of the arena that will be generated. The size is rounded up to the
+    /// nearest odd number of tiles, and to at least [`MIN_SIZE`].
+    pub fn size(&self) -> (i32, i32) {
+        (odd_size(self.width), odd_size(self.height))
+    }
+}
+
+impl ArenaLayout {
+    pub fn generate(settings: &ArenaSettings, rng: &mut impl Rng) -> Self {
+        let (width, height) = settings.size();
+
+        // Player 1 starts in the top left, and the rest go round in the same order as in
+        // `level.ldtk`.
+        let spawns = [
+            GridCoords::new(1, height - 2),
+            GridCoords::new(width - 2, 1),
+            GridCoords::new(1, 1),
+            GridCoords::new(width - 2, height - 2),
+        ];
+
+        let is_safe = |coords: GridCoords| {
+            spawns.iter().any(|spawn| {
+                let (dx, dy) = ((coords.x - spawn.x).abs(), (coords.y - spawn.y).abs());
+                // The spawn point and the tiles next to it, which make an L with the corner walls.
+                (dx <= 1 && dy == 0) || (dx == 0 && dy <= 1)
+            })
+        };
+
+        let mut walls = Vec::new();
+        let mut bombable = Vec::new();
+        // Whether each tile chosen at random is bombable, so that mirrored tiles can copy it.
+        let mut chosen = HashMap::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                let coords = GridCoords::new(x, y);
+                let border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                let pillar = x % 2 == 0 && y % 2 == 0;
+
+                if border || pillar {
+                    walls.push(coords);
+                    continue;
+                }
+                if is_safe(coords) {
+                    continue;
+                }
+
+                let original = settings.symmetry.original(coords, width, height);
+                let is_bombable = *chosen
+                    .entry(original)
+                    .or_insert_with(|| rng.gen::<f32>() < settings.density);
+                if is_bombable {
+                    bombable.push(coords);
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            walls,
+            bombable,
+            spawns,
+        }
+    }
+}
+
+impl Symmetry {
+    /// The tile which `coords` is a mirror image of. This is the tile itself if it isn't mirrored.
+    fn original(self, coords: GridCoords, width: i32, height: i32) -> GridCoords {
+        let mirror_x = GridCoords::new(coords.x.min(width - 1 - coords.x), coords.y);
+        let mirror_y = GridCoords::new(coords.x, coords.y.min(height - 1 - coords.y));
+
+        match self {
+            Self::None => coords,
+            Self::Horizontal => mirror_x,
+            Self::Vertical => mirror_y,
+            Self::Quad => GridCoords::new(mirror_x.x, mirror_y.y),
+        }
+    }
+}
+
+fn odd_size(size: i32) -> i32 {
+    let size = size.max(MIN_SIZE);
+    size + (size + 1) % 2
+}
+
+/// Marker component for the root entity of a level spawned from an [`ArenaLayout`], which is either
+/// a generated arena or an [`AsciiMap`](crate::ascii_map::AsciiMap).
+#[derive(Component, Debug)]
+pub struct GeneratedArena {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl GeneratedArena {
+    /// The size of the arena in pixels.
+    pub fn size_px(&self, tile_size: TileSize) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32) * tile_size.0
+    }
+}
+
+/// Resource containing the seed of the generated arena being played. This only exists while a
+/// generated arena is being played.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ArenaSeed(pub u64);
+
+/// Resource which makes the next generated arena use this seed, rather than one from the
+/// [`GameRng`], so that it is the same as an arena generated before.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct NextArenaSeed(pub u64);
+
+/// Run condition for when the selected level is a generated arena.
+pub fn generated_level_selected(level_selection: Res<LevelSelection>) -> bool {
+    match level_selection.as_ref() {
+        LevelSelection::Identifier(identifier) => identifier == GENERATED_LEVEL,
+        _ => false,
+    }
+}
+
+fn spawn_arena(
+    mut commands: Commands,
+    ruleset: Res<Ruleset>,
+    mut rng: ResMut<GameRng>,
+    next_seed: Option<Res<NextArenaSeed>>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let seed = match next_seed {
+        Some(next_seed) => {
+            commands.remove_resource::<NextArenaSeed>();
+            next_seed.0
+        }
+        None => rng.0.gen(),
+    };
+    let layout = ArenaLayout::generate(&ruleset.arena, &mut GameRng::from_seed(seed).0);
+    info!(
+        "generated a {}x{} arena with {} bombable tiles from seed {seed}",
+        layout.width,
+        layout.height,
+        layout.bombable.len()
+    );
+
+    commands.insert_resource(ArenaSeed(seed));
+    spawn_layout(&mut commands, &layout, &asset_server);
+    next_state.set(GameState::InGame);
+}
+
+/// Spawn the tiles and spawn points of `layout`, under a [`GeneratedArena`] root entity. These use
+/// the default [`TileSize`].
+pub fn spawn_layout(commands: &mut Commands, layout: &ArenaLayout, asset_server: &AssetServer) {
+    let tile_size = TileSize::default();
+    commands.insert_resource(tile_size);
+
+    let floor = (0..layout.height)
+        .flat_map(|y| (0..layout.width).map(move |x| GridCoords::new(x, y)))
+        .collect::<Vec<_>>();
+
+    let root = commands
+        .spawn((
+            GeneratedArena {
+                width: layout.width,
+                height: layout.height,
+            },
+            Transform::default(),
+            Visibility::default(),
+            Name::new("Generated arena"),
+        ))
+        .id();
+
+    // Layers are stacked in the same order as in `level.ldtk`.
+    for (z, identifier, tile, tiles) in [
+        (0.0, "Ground", GROUND_TILE, &floor),
+        (1.0, "Maze", MAZE_TILE, &layout.walls),
+        (2.0, "Bombable", BOMBABLE_TILE, &layout.bombable),
+    ] {
+        let layer = spawn_layer(
+            commands,
+            layout,
+            identifier,
+            z,
+            tile,
+            tiles,
+            tile_size,
+            asset_server,
+        );
+        commands.entity(root).add_child(layer);
+    }
+
+    // Spawn points are on an entity layer, like the `Players` layer of `level.ldtk`.
+    let players_layer = commands
+        .spawn((
+            Transform::default(),
+            Visibility::default(),
+            LayerMetadata {
+                c_wid: layout.width,
+                c_hei: layout.height,
+                grid_size: tile_size.0 as i32,
+                identifier: "Players".to_string(),
+                layer_instance_type: Type::Entities,
+                ..default()
+            },
+            Name::new("Players"),
+        ))
+        .id();
+    commands.entity(root).add_child(players_layer);
+
+    for (i, spawn) in layout.spawns.iter().enumerate() {
+        let spawn_point = commands
+            .spawn((
+                EntityInstance {
+                    identifier: format!("Player_{}", i + 1),
+                    grid: IVec2::new(spawn.x, layout.height - 1 - spawn.y),
+                    ..default()
+                },
+                *spawn,
+                Transform::from_translation(spawn.to_world(tile_size).extend(0.0)),
+            ))
+            .id();
+        commands.entity(players_layer).add_child(spawn_point);
+    }
+}
+
+/// Spawn a tile layer, with the same components that `bevy_ecs_ldtk` gives the layers and tiles of
+/// an LDtk level.
+#[allow(clippy::too_many_arguments)]
+fn spawn_layer(
+    commands: &mut Commands,
+    layout: &ArenaLayout,
+    identifier: &str,
+    z: f32,
+    (tileset, tile_id): (&str, u32),
+    tiles: &[GridCoords],
+    TileSize(tile_size_px): TileSize,
+    asset_server: &AssetServer,
+) -> Entity {
+    let size = TilemapSize {
+        x: layout.width as u32,
+        y: layout.height as u32,
+    };
+    let layer = commands.spawn_empty().id();
+    let mut storage = TileStorage::empty(size);
+
+    for coords in tiles {
+        let position = TilePos::new(coords.x as u32, coords.y as u32);
+        let tile = commands
+            .spawn((
+                TileBundle {
+                    position,
+                    tilemap_id: TilemapId(layer),
+                    texture_index: TileTextureIndex(tile_id),
+                    ..default()
+                },
+                *coords,
+            ))
+            .id();
+        storage.set(&position, tile);
+        commands.entity(layer).add_child(tile);
+    }
+
+    let tile_size = TilemapTileSize {
+        x: tile_size_px,
+        y: tile_size_px,
+    };
+    commands.entity(layer).insert((
+        TilemapBundle {
+            grid_size: tile_size.into(),
+            map_type: TilemapType::Square,
+            size,
+            storage,
+            texture: TilemapTexture::Single(asset_server.load(tileset)),
+            tile_size,
+            // Tiles are drawn centered on their position, so offset the layer by half a tile to
+            // line the tiles up with `GridCoords::to_world`.
+            transform: Transform::from_xyz(tile_size_px / 2.0, tile_size_px / 2.0, z),
+            ..default()
+        },
+        LayerMetadata {
+            c_wid: layout.width,
+            c_hei: layout.height,
+            grid_size: tile_size_px as i32,
+            identifier: identifier.to_string(),
+            ..default()
+        },
+        Name::new(identifier.to_string()),
+    ));
+
+    layer
+}
+
+fn despawn_arena(mut commands: Commands, arenas: Query<Entity, With<GeneratedArena>>) {
+    arenas
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+    commands.remove_resource::<ArenaSeed>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    const SYMMETRIES: [Symmetry; 4] = [
+        Symmetry::None,
+        Symmetry::Horizontal,
+        Symmetry::Vertical,
+        Symmetry::Quad,
+    ];
+
+    fn generate(symmetry: Symmetry, seed: u64) -> ArenaLayout {
+        let settings = ArenaSettings {
+            width: 13,
+            height: 9,
+            density: 1.0,
+            symmetry,
+        };
+        ArenaLayout::generate(&settings, &mut GameRng::from_seed(seed).0)
+    }
+
+    #[test]
+    fn safe_zones_are_free() {
+        for symmetry in SYMMETRIES {
+            for seed in 0..10 {
+                let layout = generate(symmetry, seed);
+                for spawn in layout.spawns {
+                    let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .map(|(dx, dy)| GridCoords::new(spawn.x + dx, spawn.y + dy));
+                    assert!(!layout.walls.contains(&spawn), "{symmetry:?} {spawn:?}");
+                    assert!(!layout.bombable.contains(&spawn), "{symmetry:?} {spawn:?}");
+                    assert!(
+                        neighbours.iter().all(|n| !layout.bombable.contains(n)),
+                        "{symmetry:?} {spawn:?}"
+                    );
+                    // The other two neighbours are the walls in the corner.
+                    let free = neighbours
+                        .iter()
+                        .filter(|n| !layout.walls.contains(n))
+                        .count();
+                    assert_eq!(free, 2, "{symmetry:?} {spawn:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bombable_tiles_are_mirrored() {
+        for symmetry in SYMMETRIES {
+            let settings = ArenaSettings {
+                density: 0.5,
+                symmetry,
+                ..default()
+            };
+            let layout = ArenaLayout::generate(&settings, &mut GameRng::from_seed(3).0);
+            let bombable = layout.bombable.iter().copied().collect::<HashSet<_>>();
+            for coords in &layout.bombable {
+                let original = symmetry.original(*coords, layout.width, layout.height);
+                assert!(bombable.contains(&original), "{symmetry:?} {coords:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let settings = ArenaSettings::default();
+        let generate = |seed| ArenaLayout::generate(&settings, &mut GameRng::from_seed(seed).0);
+
+        let (first, second) = (generate(7), generate(7));
+        assert_eq!(first.walls, second.walls);
+        assert_eq!(first.bombable, second.bombable);
+        assert_eq!(first.spawns, second.spawns);
+        assert_ne!(first.bombable, generate(8).bombable);
+    }
+}
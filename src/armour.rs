@@ -0,0 +1,125 @@
+//! Bombable tiles which take more than one blast to destroy. These are authored in LDtk with the
+//! custom data of a tile in the tileset, using one `key=value` setting per line, for example:
+//!
+//! ```text
+//! hits=3
+//! damaged=17,18
+//! ```
+//!
+//! Here `hits` is the number of blasts needed to destroy the tile, and `damaged` is the list of tile
+//! IDs from the same tileset to show after each hit. Only tiles on the `Bombable` layer are read, and
+//! tiles without a `hits` setting are destroyed by a single blast.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::TileTextureIndex;
+use serde_derive::{Deserialize, Serialize};
+
+pub struct ArmourPlugin;
+
+impl Plugin for ArmourPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, init_armour);
+    }
+}
+
+/// Hit points for a bombable tile.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Armour {
+    hits: u32,
+    /// Tile IDs to show after each hit, in order. The last one is kept if there are fewer of these
+    /// than hits.
+    damaged_tiles: Vec<u32>,
+}
+
+impl Armour {
+    /// Damage the tile, swapping its sprite. Returns `true` if the tile should be destroyed.
+    pub fn hit(&mut self, texture_index: &mut TileTextureIndex) -> bool {
+        self.hits = self.hits.saturating_sub(1);
+        if self.hits == 0 {
+            return true;
+        }
+
+        if !self.damaged_tiles.is_empty() {
+            texture_index.0 = self.damaged_tiles.remove(0);
+        }
+        false
+    }
+}
+
+impl TryFrom<&TileMetadata> for Armour {
+    type Error = String;
+
+    fn try_from(metadata: &TileMetadata) -> Result<Self, Self::Error> {
+        let mut hits = None;
+        let mut damaged_tiles = Vec::new();
+
+        for (key, value) in metadata
+            .data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split_once('=')
+                    .ok_or_else(|| format!("expected key=value: {line}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+        {
+            match key.trim() {
+                "hits" => {
+                    hits = Some(
+                        value
+                            .trim()
+                            .parse()
+                            .map_err(|e| format!("invalid hits {value}: {e}"))?,
+                    );
+                }
+                "damaged" => {
+                    damaged_tiles = value
+                        .split(',')
+                        .map(|id| {
+                            id.trim()
+                                .parse()
+                                .map_err(|e| format!("invalid damaged tile {id}: {e}"))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => return Err(format!("unrecognised tile setting: {key}")),
+            }
+        }
+
+        Ok(Self {
+            hits: hits.ok_or("missing hits")?,
+            damaged_tiles,
+        })
+    }
+}
+
+/// Whether the custom data of a tile sets its armour, rather than being for something else.
+fn has_armour(metadata: &TileMetadata) -> bool {
+    metadata
+        .data
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, _)| key.trim() == "hits")
+}
+
+/// Add [`Armour`] to newly spawned tiles on the `Bombable` layer with a `hits` setting.
+fn init_armour(
+    mut commands: Commands,
+    tiles: Query<(Entity, &Parent, &TileMetadata), Added<TileMetadata>>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+) {
+    for (entity, _, metadata) in tiles.iter().filter(|(_, parent, metadata)| {
+        ldtk_layer_meta_q
+            .get(***parent)
+            .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+            && has_armour(metadata)
+    }) {
+        match Armour::try_from(metadata) {
+            Ok(armour) => {
+                commands.entity(entity).insert(armour);
+            }
+            Err(e) => warn!("failed to parse tile custom data {:?}: {e}", metadata.data),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! A plain text level format, as a lighter alternative to LDtk for quick experiments and test
+//! fixtures. Each character is a tile, with the first line at the top:
+//!
+//! - `#` is a wall;
+//! - `x` is a bombable tile;
+//! - `1` to `4` are the spawn points of each player;
+//! - `.` is an empty floor tile.
+//!
+//! Every line must be the same length, the edges of the map must all be walls, and every spawn
+//! point must be used once. For example:
+//!
+//! ```text
+//! #######
+//! #1.x.4#
+//! #.#x#.#
+//! #xx.xx#
+//! #.#x#.#
+//! #3.x.2#
+//! #######
+//! ```
+//!
+//! Maps are files ending in `.map` in the assets folder. A map is played by selecting its path as
+//! the level identifier, such as with `--map maps/example.map`, and is spawned in the same way as a
+//! generated arena, so that the rest of the gameplay doesn't need to know where the level came
+//! from.
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadState},
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::{
+    arena::{spawn_layout, ArenaLayout},
+    netplay::MAX_PLAYERS,
+    GameState,
+};
+
+#[cfg(test)]
+use crate::ldtk::TileSize;
+
+pub struct AsciiMapPlugin;
+
+impl Plugin for AsciiMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<AsciiMap>()
+            .register_asset_loader(AsciiMapLoader)
+            .add_systems(
+                OnEnter(GameState::LoadingLevel),
+                load_map.run_if(ascii_map_selected),
+            )
+            .add_systems(
+                Update,
+                spawn_map
+                    .run_if(in_state(GameState::LoadingLevel).and(resource_exists::<LoadingMap>)),
+            );
+    }
+}
+
+/// The file extension of ASCII maps.
+const EXTENSION: &str = "map";
+
+/// A level loaded from a plain text map.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct AsciiMap(pub ArenaLayout);
+
+impl AsciiMap {
+    /// Parse the text of a map.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lines = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+
+        let height = lines.len() as i32;
+        let width = lines.first().map_or(0, |line| line.chars().count()) as i32;
+        if width == 0 {
+            return Err("the map is empty".to_string());
+        }
+
+        let mut walls = Vec::new();
+        let mut bombable = Vec::new();
+        let mut spawns = [None; MAX_PLAYERS];
+
+        for (row, line) in lines.iter().enumerate() {
+            let line_number = row + 1;
+            if line.chars().count() as i32 != width {
+                return Err(format!(
+                    "line {line_number} is {} tiles wide, but the first line is {width}",
+                    line.chars().count()
+                ));
+            }
+
+            // The first line is the top of the map, but `GridCoords` start at the bottom.
+            let y = height - 1 - row as i32;
+            for (x, tile) in line.chars().enumerate() {
+                let coords = GridCoords::new(x as i32, y);
+                match tile {
+                    '#' => walls.push(coords),
+                    'x' => bombable.push(coords),
+                    '.' => {}
+                    '1'..='4' => {
+                        let player = tile as usize - '1' as usize;
+                        if spawns[player].replace(coords).is_some() {
+                            return Err(format!("spawn point {tile} is used more than once"));
+                        }
+                    }
+                    _ => {
+                        return Err(format!(
+                            "unknown tile {tile:?} on line {line_number}, column {}",
+                            x + 1
+                        ))
+                    }
+                }
+
+                let edge = row == 0 || y == 0 || x == 0 || x as i32 == width - 1;
+                if edge && tile != '#' {
+                    return Err(format!(
+                        "the map isn't closed, as line {line_number}, column {} is {tile:?} \
+                         rather than a wall",
+                        x + 1
+                    ));
+                }
+            }
+        }
+
+        if let Some(player) = (1..=MAX_PLAYERS).find(|player| spawns[player - 1].is_none()) {
+            return Err(format!("missing spawn point {player}"));
+        }
+
+        Ok(Self(ArenaLayout {
+            width,
+            height,
+            walls,
+            bombable,
+            spawns: spawns.map(Option::unwrap_or_default),
+        }))
+    }
+}
+
+#[derive(Default)]
+struct AsciiMapLoader;
+
+impl AssetLoader for AsciiMapLoader {
+    type Asset = AsciiMap;
+    type Settings = ();
+    type Error = String;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| format!("failed to read map: {e}"))?;
+        let text = String::from_utf8(bytes).map_err(|e| format!("map isn't UTF-8: {e}"))?;
+        AsciiMap::parse(&text)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[EXTENSION]
+    }
+}
+
+/// Run condition for when the selected level is the path of an [`AsciiMap`].
+pub fn ascii_map_selected(level_selection: Res<LevelSelection>) -> bool {
+    match level_selection.as_ref() {
+        LevelSelection::Identifier(identifier) => is_ascii_map(identifier),
+        _ => false,
+    }
+}
+
+/// Whether a level identifier is the path of an [`AsciiMap`]. LDtk identifiers can't contain a
+/// `.`, so this can't be mistaken for an LDtk level.
+pub fn is_ascii_map(identifier: &str) -> bool {
+    identifier.ends_with(&format!(".{EXTENSION}"))
+}
+
+/// Resource holding the map being loaded for the next round.
+#[derive(Resource, Debug)]
+struct LoadingMap(Handle<AsciiMap>);
+
+fn load_map(
+    mut commands: Commands,
+    level_selection: Res<LevelSelection>,
+    asset_server: Res<AssetServer>,
+) {
+    if let LevelSelection::Identifier(path) = level_selection.as_ref() {
+        commands.insert_resource(LoadingMap(asset_server.load(path.clone())));
+    }
+}
+
+/// Spawn the map once it has loaded, and start the round. Maps which can't be loaded go back to
+/// the menu instead.
+fn spawn_map(
+    mut commands: Commands,
+    loading: Res<LoadingMap>,
+    maps: Res<Assets<AsciiMap>>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if let Some(map) = maps.get(&loading.0) {
+        spawn_layout(&mut commands, &map.0, &asset_server);
+        next_state.set(GameState::InGame);
+    } else if let LoadState::Failed(e) = asset_server.load_state(&loading.0) {
+        error!("can't play map: {e}");
+        next_state.set(GameState::MainMenu);
+    } else {
+        return;
+    }
+    commands.remove_resource::<LoadingMap>();
+}
+
+/// Spawn the walls and bombable tiles of a map for a test, with the same `GridCoords`, parents and
+/// `LayerMetadata` as the tiles of a level, but without a tilemap or any assets.
+#[cfg(test)]
+pub fn spawn_test_map(world: &mut World, text: &str) -> ArenaLayout {
+    let AsciiMap(layout) = AsciiMap::parse(text).expect("invalid test map");
+    world.insert_resource(TileSize::default());
+
+    for (identifier, tiles) in [("Maze", &layout.walls), ("Bombable", &layout.bombable)] {
+        let layer = world
+            .spawn(LayerMetadata {
+                identifier: identifier.to_string(),
+                ..default()
+            })
+            .id();
+        for coords in tiles {
+            world.spawn(*coords).set_parent(layer);
+        }
+    }
+
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "
+#######
+#1.x.4#
+#.#x#.#
+#3...2#
+#######
+";
+
+    #[test]
+    fn parses_the_first_line_as_the_top() {
+        let AsciiMap(layout) = AsciiMap::parse(MAP).unwrap();
+
+        assert_eq!((layout.width, layout.height), (7, 5));
+        assert_eq!(
+            layout.spawns,
+            [
+                GridCoords::new(1, 3),
+                GridCoords::new(5, 1),
+                GridCoords::new(1, 1),
+                GridCoords::new(5, 3),
+            ]
+        );
+        assert_eq!(
+            layout.bombable,
+            [GridCoords::new(3, 3), GridCoords::new(3, 2)]
+        );
+        assert!(layout.walls.contains(&GridCoords::new(2, 2)));
+        assert!(!layout.walls.contains(&GridCoords::new(2, 1)));
+    }
+
+    #[test]
+    fn parses_the_example_map() {
+        assert!(AsciiMap::parse(include_str!("../assets/maps/example.map")).is_ok());
+    }
+
+    #[test]
+    fn rejects_ragged_lines() {
+        let error = AsciiMap::parse("#######\n#1.x.4#\n#.#x#.\n#3...2#\n#######").unwrap_err();
+        assert!(error.contains("line 3"), "{error}");
+    }
+
+    #[test]
+    fn rejects_duplicate_spawns() {
+        let error = AsciiMap::parse(&MAP.replace('4', "1")).unwrap_err();
+        assert!(
+            error.contains("spawn point 1 is used more than once"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_missing_spawns() {
+        let error = AsciiMap::parse(&MAP.replace('4', ".")).unwrap_err();
+        assert!(error.contains("missing spawn point 4"), "{error}");
+    }
+
+    #[test]
+    fn rejects_open_borders() {
+        for (line, column) in [(1, 4), (3, 7), (5, 1)] {
+            let mut lines = MAP.trim().lines().map(str::to_string).collect::<Vec<_>>();
+            lines[line - 1].replace_range(column - 1..column, ".");
+
+            let error = AsciiMap::parse(&lines.join("\n")).unwrap_err();
+            assert!(
+                error.contains(&format!("line {line}, column {column}")),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_maps() {
+        assert!(AsciiMap::parse("\n\n").is_err());
+    }
+}
@@ -2,7 +2,7 @@ use bevy::prelude::*;
 use bevy_kira_audio::prelude::*;
 use rand::prelude::*;
 
-use crate::{config::Config, GameRng, GameState};
+use crate::{config::Config, netplay::NetplaySet, rules::LevelRules, CosmeticRng, GameState};
 
 pub struct AudioPlugin;
 
@@ -16,7 +16,12 @@ impl Plugin for AudioPlugin {
             .add_systems(OnEnter(GameState::InGame), start_fight_bgm)
             .add_systems(OnExit(GameState::MainMenu), stop_bgm)
             .add_systems(OnExit(GameState::InGame), stop_bgm)
-            .add_systems(Update, play_sfx.run_if(in_state(GameState::InGame)));
+            .add_systems(
+                Update,
+                play_sfx
+                    .after(NetplaySet::Effects)
+                    .run_if(in_state(GameState::InGame)),
+            );
     }
 }
 
@@ -54,9 +59,12 @@ fn start_fight_bgm(
     audio: Res<AudioChannel<BgmChannel>>,
     bgm: Res<Bgm>,
     assets: Res<AssetServer>,
-    mut rng: ResMut<GameRng>,
+    rules: Res<LevelRules>,
+    mut rng: ResMut<CosmeticRng>,
 ) {
-    if let Some(audio_path) = bgm.in_game.choose(&mut rng.0) {
+    if let Some(audio_path) = &rules.0.fight_music {
+        audio.play(assets.load(audio_path.clone())).looped();
+    } else if let Some(audio_path) = bgm.in_game.choose(&mut rng.0) {
         audio.play(assets.load(*audio_path)).looped();
     } else {
         warn!("no paths to music files found in Bgm.in_game");
@@ -70,7 +78,7 @@ fn stop_bgm(audio: Res<AudioChannel<BgmChannel>>) {
 fn play_sfx(
     audio: Res<AudioChannel<SfxChannel>>,
     sfx: Res<Sfx>,
-    mut rng: ResMut<GameRng>,
+    mut rng: ResMut<CosmeticRng>,
     mut ev_sfx: EventReader<PlaySfx>,
 ) {
     use PlaySfx::*;
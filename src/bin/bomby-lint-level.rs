@@ -0,0 +1,37 @@
+//! Checks LDtk projects for broken levels. See the [`lint`](bomby::lint) module.
+
+use bomby::{cli::LintArgs, lint};
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args = LintArgs::parse();
+
+    let problems = match lint::lint_file(&args.path) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&problems).expect("failed to serialise problems")
+        );
+    } else if problems.is_empty() {
+        println!("{}: no problems found", args.path.display());
+    } else {
+        for problem in problems.iter() {
+            println!("{}: {problem}", args.path.display());
+        }
+        println!("{} problems found", problems.len());
+    }
+
+    if problems.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
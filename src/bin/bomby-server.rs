@@ -0,0 +1,9 @@
+//! Dedicated headless server. See the [`server`](bomby::server) module.
+
+use bomby::{cli::ServerArgs, config, server};
+
+fn main() {
+    let args = ServerArgs::parse();
+    let config = config::load_config();
+    server::run(config, &args);
+}
@@ -1,81 +1,141 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::{TileTextureIndex, TileVisible};
+use bevy_ggrs::AddRollbackCommandExtension;
 use leafwing_input_manager::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::{
+    armour::Armour,
     audio::PlaySfx,
     camera::CameraTrauma,
-    ldtk::{GridNormalise, ToGrid},
-    player::{Player, PlayerAction},
+    ldtk::{Blocker, Destroyed, GridNormalise, TileSize, ToGrid},
+    netplay::Gameplay,
+    player::{Eliminated, Player, PlayerAction},
+    rules::LevelRules,
+    teleporter::Teleportable,
     z_sort::{ZSort, PLAYER_Z},
     GameState,
 };
 
 pub struct BombPlugin;
 
-const MAX_BOMBS_PER_PLAYER: u8 = 2;
-const BOMB_TIMER_SECS: f32 = 1.5;
-
 /// The amount of trauma to send to the camera on an explosion.
-const BOMB_TRAUMA: f32 = 0.3;
+pub const BOMB_TRAUMA: f32 = 0.3;
+
+/// The size of the bomb sprite in pixels, drawn for the default [`TileSize`].
+const BOMB_SPRITE_SIZE: Vec2 = Vec2::new(32.0, 33.0);
 
 impl Plugin for BombPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreStartup, load_graphics).add_systems(
-            Update,
-            (spawn_bombs, update_bombs, animate_bombs).run_if(in_state(GameState::InGame)),
-        );
+        app.add_event::<Explosion>()
+            .add_systems(PreStartup, load_graphics)
+            .add_systems(
+                Gameplay,
+                (spawn_bombs, update_bombs)
+                    .in_set(BombSet)
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(Update, animate_bombs.run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), despawn_bombs);
     }
 }
 
-#[derive(Component, Debug)]
+/// The systems which place and explode bombs. Systems reading [`Explosion`] events should run after
+/// these, so that an explosion is handled on the frame it happens, which is needed for rollback.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BombSet;
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct Bomb {
-    spawner: Entity,
+    /// The index of the player who placed the bomb.
+    owner: usize,
     timer: Timer,
 }
 
+impl Bomb {
+    /// The time in seconds until the bomb explodes.
+    pub fn remaining_secs(&self) -> f32 {
+        self.timer.remaining_secs()
+    }
+
+    /// The index of the player who placed the bomb.
+    pub fn owner(&self) -> usize {
+        self.owner
+    }
+}
+
+/// Event sent when a bomb explodes, containing every tile reached by the blast.
+#[derive(Event, Debug)]
+pub struct Explosion {
+    /// The tile the bomb exploded on.
+    pub origin: GridCoords,
+    pub tiles: Vec<GridCoords>,
+    /// The bombable tiles destroyed by the blast.
+    pub destroyed_tiles: Vec<GridCoords>,
+}
+
 /// This is used to keep track of the current number of active bombs a player (or other bomb
 /// wielding entity) has placed.
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct CountBombs(u8);
 
+impl CountBombs {
+    /// Whether another bomb can be placed without going over the limit of `max_bombs`.
+    pub fn can_place(&self, max_bombs: u8) -> bool {
+        self.0 < max_bombs
+    }
+
+    /// Give back a bomb once it has exploded or been put out, so that another can be placed.
+    pub fn release(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+    }
+}
+
 fn spawn_bombs(
     mut commands: Commands,
-    mut players: Query<
-        (
-            Entity,
-            &ActionState<PlayerAction>,
-            &Transform,
-            &mut CountBombs,
-        ),
-        With<Player>,
-    >,
+    mut players: Query<(
+        &Player,
+        &ActionState<PlayerAction>,
+        &Transform,
+        &mut CountBombs,
+    )>,
     texture_atlas: Res<BombSprite>,
     bombs: Query<&Transform, With<Bomb>>,
+    rules: Res<LevelRules>,
     mut ev_sfx: EventWriter<PlaySfx>,
+    tile_size: Res<TileSize>,
 ) {
-    for (entity, translation, mut count_bombs) in players
+    for (owner, translation, mut count_bombs) in players
         .iter_mut()
         .filter(|(_, action_state, _, _)| action_state.just_pressed(&PlayerAction::Bomb))
-        .filter(|(_, _, _, count_bombs)| count_bombs.0 < MAX_BOMBS_PER_PLAYER)
+        .filter(|(_, _, _, count_bombs)| count_bombs.can_place(rules.0.max_bombs))
         .filter(|(_, _, translation, _)| {
             bombs.iter().all(|bomb_transform| {
-                bomb_transform.translation.to_grid() != translation.translation.to_grid()
+                bomb_transform.translation.to_grid(*tile_size)
+                    != translation.translation.to_grid(*tile_size)
             })
         })
-        .map(|(entity, _, transform, count_bombs)| {
-            (entity, transform.translation.grid_normalised(), count_bombs)
+        .map(|(player, _, transform, count_bombs)| {
+            (
+                player.0,
+                transform.translation.grid_normalised(*tile_size),
+                count_bombs,
+            )
         })
     {
-        commands.spawn((
-            Sprite::from_atlas_image(texture_atlas.0.clone(), texture_atlas.1.clone().into()),
-            Transform::from_translation(translation.extend(PLAYER_Z) + Vec3::Y * 2.0),
-            ZSort(PLAYER_Z),
+        spawn_bomb(
+            &mut commands,
+            &texture_atlas,
+            // Lift the bomb a little so it sits in the middle of the tile.
+            translation.extend(PLAYER_Z) + Vec3::Y * tile_size.0 / 16.0,
             Bomb {
-                spawner: entity,
-                timer: Timer::from_seconds(BOMB_TIMER_SECS, TimerMode::Once),
+                owner,
+                timer: Timer::from_seconds(rules.0.bomb_timer_secs, TimerMode::Once),
             },
-        ));
+            Teleportable::new(translation.to_grid(*tile_size)),
+            *tile_size,
+        );
 
         count_bombs.0 += 1;
 
@@ -83,69 +143,178 @@ fn spawn_bombs(
     }
 }
 
-/// Tick the bomb timers. If fully elapsed, destroy the bomb and surrounding bombable tiles.
+/// Spawn a bomb with its sprite at `translation`.
+pub fn spawn_bomb(
+    commands: &mut Commands,
+    texture_atlas: &BombSprite,
+    translation: Vec3,
+    bomb: Bomb,
+    teleportable: Teleportable,
+    tile_size: TileSize,
+) {
+    commands
+        .spawn((
+            Sprite {
+                custom_size: Some(BOMB_SPRITE_SIZE * tile_size.scale()),
+                ..Sprite::from_atlas_image(texture_atlas.0.clone(), texture_atlas.1.clone().into())
+            },
+            Transform::from_translation(translation),
+            ZSort(PLAYER_Z),
+            bomb,
+            teleportable,
+        ))
+        .add_rollback();
+}
+
+/// Tick the bomb timers. If fully elapsed, destroy the bomb and damage surrounding bombable tiles.
 #[allow(clippy::too_many_arguments)]
 fn update_bombs(
     mut commands: Commands,
     mut bombs: Query<(Entity, &mut Bomb, &Transform)>,
-    mut players: Query<(Entity, &mut CountBombs, &Transform), With<Player>>,
+    mut players: Query<(Entity, &Player, &mut CountBombs, &Transform)>,
     mut ev_trauma: EventWriter<CameraTrauma>,
     mut ev_sfx: EventWriter<PlaySfx>,
+    mut ev_explosion: EventWriter<Explosion>,
     time: Res<Time>,
-    tiles: Query<(Entity, &Parent, &GridCoords)>,
+    tiles: Query<(Entity, &Parent, &GridCoords), Without<Destroyed>>,
+    mut armour: Query<(&mut Armour, &mut TileTextureIndex)>,
+    blockers: Query<&GridCoords, With<Blocker>>,
     ldtk_layer_meta_q: Query<&LayerMetadata>,
+    rules: Res<LevelRules>,
+    tile_size: Res<TileSize>,
 ) {
-    for (entity, mut bomb, bomb_coords) in bombs
+    let mut exploded = bombs
         .iter_mut()
-        .map(|(e, b, transform)| (e, b, transform.translation.to_grid()))
-    {
-        bomb.timer.tick(time.delta());
-
-        if bomb.timer.just_finished() {
-            commands.entity(entity).despawn_recursive();
+        .filter_map(|(entity, mut bomb, transform)| {
+            bomb.timer.tick(time.delta());
+            bomb.timer.just_finished().then(|| {
+                (
+                    entity,
+                    bomb.owner,
+                    transform.translation.to_grid(*tile_size),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    // Query order is not the same on every netplay peer, so handle explosions in a fixed order.
+    exploded.sort_by_key(|(_, _, coords)| (coords.x, coords.y));
 
-            // Decrement `CountBombs` component on the player that spawned the bomb
-            if let Ok((_, mut bomb_count, _)) = players.get_mut(bomb.spawner) {
-                bomb_count.0 -= 1;
-            }
+    if exploded.is_empty() {
+        return;
+    }
 
-            // Get tiles within 1 orthogonal tile
-            let affected_tiles = tiles
-                .iter()
-                .filter(|(_, _, coords)| {
-                    let displacement = **coords - bomb_coords;
-                    displacement.x.pow(2) + displacement.y.pow(2) <= 1
-                })
-                .collect::<Vec<_>>();
-
-            // Destroy bombable tiles within 1 orthogonal tile
-            for tile in affected_tiles.iter().filter(|(_, parent, coords)| {
+    let layer_tiles = |identifier: &str| {
+        tiles
+            .iter()
+            .filter(|(_, parent, coords)| {
                 ldtk_layer_meta_q.get(***parent).map_or_else(
                     |_| {
                         warn!("LDtk tile not child of a layer with coords: {coords:?}");
                         false
                     },
-                    |ldtk_layer| ldtk_layer.identifier == "Bombable",
+                    |ldtk_layer| ldtk_layer.identifier == identifier,
                 )
-            }) {
-                commands.entity(tile.0).despawn_recursive();
+            })
+            .map(|(entity, _, coords)| (entity, *coords))
+            .collect::<Vec<_>>()
+    };
+
+    let walls = layer_tiles("Maze")
+        .into_iter()
+        .map(|(_, coords)| coords)
+        .chain(blockers.iter().copied())
+        .collect::<Vec<_>>();
+    let bombable = layer_tiles("Bombable");
+    let bombable_coords = bombable
+        .iter()
+        .map(|(_, coords)| *coords)
+        .collect::<Vec<_>>();
+
+    for (entity, owner, bomb_coords) in exploded {
+        commands.entity(entity).despawn_recursive();
+
+        // Decrement `CountBombs` component on the player that spawned the bomb
+        if let Some((_, _, mut bomb_count, _)) = players
+            .iter_mut()
+            .find(|(_, player, _, _)| player.0 == owner)
+        {
+            bomb_count.release();
+        }
+
+        let affected_tiles =
+            blast_tiles(bomb_coords, rules.0.blast_range, &walls, &bombable_coords);
+
+        // Damage bombable tiles caught in the blast, destroying them if they have no armour left
+        let mut destroyed_tiles = Vec::new();
+        for (tile, coords) in bombable
+            .iter()
+            .filter(|(_, coords)| affected_tiles.contains(coords))
+        {
+            let destroyed = armour
+                .get_mut(*tile)
+                .map_or(true, |(mut armour, mut texture_index)| {
+                    armour.hit(&mut texture_index)
+                });
+
+            if destroyed {
+                commands
+                    .entity(*tile)
+                    .insert((Destroyed, TileVisible(false)));
+                destroyed_tiles.push(*coords);
             }
+        }
 
-            // Blow up players. Destroying for now, in future probably add a marker component which
-            // then causes other behaviour
-            for (entity, _, _) in players.iter().filter(|(_, _, player_transform)| {
-                affected_tiles
-                    .iter()
-                    .any(|(_, _, coords)| player_transform.translation.to_grid() == **coords)
-            }) {
-                ev_sfx.send(PlaySfx::PlayerDeath);
-                commands.entity(entity).despawn_recursive();
+        // Blow up players
+        for (entity, _, _, _) in players.iter().filter(|(_, _, _, player_transform)| {
+            affected_tiles.contains(&player_transform.translation.to_grid(*tile_size))
+        }) {
+            commands.entity(entity).try_insert(Eliminated {
+                killer: Some(owner),
+            });
+        }
+
+        ev_sfx.send(PlaySfx::BombExplosion);
+        ev_trauma.send(CameraTrauma(BOMB_TRAUMA));
+        ev_explosion.send(Explosion {
+            origin: bomb_coords,
+            tiles: affected_tiles,
+            destroyed_tiles,
+        });
+    }
+}
+
+/// Get the tiles reached by a blast from `origin`. The blast travels `range` tiles in each
+/// orthogonal direction, stopping before walls and at the first bombable tile it reaches.
+pub fn blast_tiles(
+    origin: GridCoords,
+    range: i32,
+    walls: &[GridCoords],
+    bombable: &[GridCoords],
+) -> Vec<GridCoords> {
+    let mut blast = vec![origin];
+
+    for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+        for distance in 1..=range {
+            let coords = GridCoords::new(origin.x + dx * distance, origin.y + dy * distance);
+            if walls.contains(&coords) {
+                break;
             }
 
-            ev_sfx.send(PlaySfx::BombExplosion);
-            ev_trauma.send(CameraTrauma(BOMB_TRAUMA));
+            blast.push(coords);
+
+            if bombable.contains(&coords) {
+                break;
+            }
         }
     }
+
+    blast
+}
+
+fn despawn_bombs(mut commands: Commands, bombs: Query<Entity, With<Bomb>>) {
+    bombs
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
 }
 
 /// Do not tick the bomb timer anywhere other than `update_bombs`.
@@ -169,7 +338,7 @@ fn load_graphics(
 ) {
     let image = assets.load("Bomb.png");
     let layout = TextureAtlasLayout::from_grid(
-        UVec2::new(32, 33),
+        BOMB_SPRITE_SIZE.as_uvec2(),
         1,
         1,
         Some(UVec2::ZERO),
@@ -178,3 +347,129 @@ fn load_graphics(
     let layout_handle = texture_atlases.add(layout);
     commands.insert_resource(BombSprite(image, layout_handle));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ascii_map::spawn_test_map, rules::Ruleset};
+
+    use std::time::Duration;
+
+    const MAP: &str = "
+#######
+#1.x.4#
+#.#x#.#
+#.....#
+#.#x#.#
+#3.x.2#
+#######
+";
+
+    /// An app which only runs `update_bombs`, on [`MAP`].
+    fn app(blast_range: i32) -> App {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(LevelRules(Ruleset {
+                blast_range,
+                ..default()
+            }))
+            .add_event::<CameraTrauma>()
+            .add_event::<PlaySfx>()
+            .add_event::<Explosion>()
+            .add_systems(Update, update_bombs);
+        spawn_test_map(app.world_mut(), MAP);
+        app
+    }
+
+    fn translation(coords: GridCoords) -> Vec3 {
+        coords.to_world(TileSize::default()).extend(PLAYER_Z)
+    }
+
+    fn spawn_player(app: &mut App, player: usize, coords: GridCoords, bombs: u8) -> Entity {
+        app.world_mut()
+            .spawn((
+                Player(player),
+                CountBombs(bombs),
+                Transform::from_translation(translation(coords)),
+            ))
+            .id()
+    }
+
+    fn spawn_test_bomb(app: &mut App, owner: usize, coords: GridCoords) {
+        app.world_mut().spawn((
+            Bomb {
+                owner,
+                timer: Timer::from_seconds(1.0, TimerMode::Once),
+            },
+            Transform::from_translation(translation(coords)),
+        ));
+    }
+
+    /// Advance the time by `secs` and run `update_bombs`, returning the explosions.
+    fn step(app: &mut App, secs: f32) -> Vec<Explosion> {
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(secs));
+        app.update();
+        app.world_mut()
+            .resource_mut::<Events<Explosion>>()
+            .drain()
+            .collect()
+    }
+
+    fn destroyed_tiles(app: &mut App) -> Vec<GridCoords> {
+        let mut destroyed = app
+            .world_mut()
+            .query_filtered::<&GridCoords, With<Destroyed>>();
+        destroyed.iter(app.world()).copied().collect()
+    }
+
+    #[test]
+    fn bombs_explode_when_their_timer_runs_out() {
+        let mut app = app(2);
+        let owner = spawn_player(&mut app, 0, GridCoords::new(1, 3), 1);
+        let safe = spawn_player(&mut app, 1, GridCoords::new(1, 5), 0);
+        spawn_test_bomb(&mut app, 0, GridCoords::new(3, 3));
+
+        assert!(step(&mut app, 0.5).is_empty());
+
+        let explosions = step(&mut app, 0.5);
+        assert_eq!(explosions.len(), 1);
+        assert_eq!(explosions[0].origin, GridCoords::new(3, 3));
+
+        // The blast stops at the first bombable tile it reaches.
+        let mut destroyed = destroyed_tiles(&mut app);
+        destroyed.sort_by_key(|coords| (coords.x, coords.y));
+        assert_eq!(destroyed, [GridCoords::new(3, 2), GridCoords::new(3, 4)]);
+        assert_eq!(explosions[0].destroyed_tiles.len(), 2);
+
+        let world = app.world_mut();
+        assert_eq!(world.query::<&Bomb>().iter(world).count(), 0);
+        assert_eq!(world.get::<CountBombs>(owner).unwrap().0, 0);
+        assert_eq!(
+            world.get::<Eliminated>(owner).and_then(|e| e.killer),
+            Some(0)
+        );
+        assert!(world.get::<Eliminated>(safe).is_none());
+    }
+
+    #[test]
+    fn blasts_stop_at_walls() {
+        let mut app = app(3);
+        spawn_test_bomb(&mut app, 0, GridCoords::new(1, 4));
+
+        let explosions = step(&mut app, 1.0);
+        assert_eq!(explosions.len(), 1);
+        assert_eq!(
+            explosions[0].tiles,
+            [
+                GridCoords::new(1, 4),
+                GridCoords::new(1, 5),
+                GridCoords::new(1, 3),
+                GridCoords::new(1, 2),
+                GridCoords::new(1, 1),
+            ]
+        );
+        assert!(destroyed_tiles(&mut app).is_empty());
+    }
+}
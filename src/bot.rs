@@ -0,0 +1,422 @@
+//! AI-controlled bot players. Bots drive the same `ActionState<PlayerAction>` as human players do
+//! through their `InputMap`, so all of the movement, collision and bomb code is shared.
+//!
+//! Every so often, a bot builds a danger map from the active bombs and plans a path through the
+//! level. In order of priority, a bot will:
+//!
+//! 1. Run to the nearest safe tile if it is about to be caught in an explosion.
+//! 2. Place a bomb if it would destroy a bombable tile or catch an opponent, and there is somewhere
+//!    safe to run to afterwards.
+//! 3. Walk to the nearest tile where it could do that.
+//!
+//! There are no pickups in the game yet, so bots do not look for them.
+//!
+//! How well a bot plays is set by its [`BotDifficulty`].
+//!
+//! Bots are driven before the gameplay runs, so they use their own [`BotRng`] rather than the
+//! [`GameRng`]. This is seeded from the [`GameRng`] at the start of each round, whether or not
+//! there are any bots, so that replays, which don't run the bots, use the [`GameRng`] in exactly the
+//! same way as the game they were recorded from.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use leafwing_input_manager::prelude::*;
+use rand::{prelude::*, rngs::SmallRng};
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    bomb::{blast_tiles, Bomb, CountBombs},
+    ldtk::{Blocker, Destroyed, TileSize, ToGrid, ToWorld},
+    player::{spawn_offset, Player, PlayerAction},
+    rules::LevelRules,
+    GameRng, GameState,
+};
+
+pub struct BotPlugin;
+
+/// The maximum number of tiles a bot will consider running to escape its own bomb.
+const MAX_ESCAPE_STEPS: usize = 4;
+
+/// How close, in pixels, a bot needs to be to its target position on a tile to have reached it.
+const ARRIVE_DISTANCE: f32 = 3.0;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), seed_bot_rng)
+            .add_systems(
+                PreUpdate,
+                drive_bots
+                    .in_set(InputManagerSystem::ManualControl)
+                    .run_if(in_state(GameState::InGame).and(resource_exists::<BotRng>)),
+            );
+    }
+}
+
+/// Resource for the RNG used by bots.
+#[derive(Resource)]
+struct BotRng(SmallRng);
+
+fn seed_bot_rng(mut commands: Commands, mut rng: ResMut<GameRng>) {
+    commands.insert_resource(BotRng(SmallRng::seed_from_u64(rng.0.gen())));
+}
+
+/// Skill presets for bots.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    fn skill(self) -> BotSkill {
+        match self {
+            Self::Easy => BotSkill {
+                reaction_secs: 0.4,
+                lookahead_secs: 1.0,
+                chain_trap: false,
+                aim_error: 0.3,
+            },
+            Self::Normal => BotSkill {
+                reaction_secs: 0.2,
+                lookahead_secs: 1.5,
+                chain_trap: false,
+                aim_error: 0.1,
+            },
+            Self::Hard => BotSkill {
+                reaction_secs: 0.05,
+                lookahead_secs: f32::INFINITY,
+                chain_trap: true,
+                aim_error: 0.0,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BotSkill {
+    /// How often the bot re-plans, in seconds.
+    reaction_secs: f32,
+    /// Bombs which will explode later than this many seconds from now are ignored when deciding
+    /// which tiles are dangerous.
+    lookahead_secs: f32,
+    /// Whether the bot places bombs to cut off the escape of opponents who are already in danger.
+    chain_trap: bool,
+    /// The chance that the bot places a bomb one tile short of where it meant to.
+    aim_error: f64,
+}
+
+/// Component for a player controlled by the bot AI.
+#[derive(Component, Debug)]
+pub struct Bot {
+    skill: BotSkill,
+    /// The tiles the bot is walking through, in order.
+    path: VecDeque<GridCoords>,
+    think_timer: Timer,
+}
+
+impl Bot {
+    pub fn new(difficulty: BotDifficulty) -> Self {
+        let skill = difficulty.skill();
+        Self {
+            think_timer: Timer::from_seconds(skill.reaction_secs, TimerMode::Repeating),
+            skill,
+            path: VecDeque::new(),
+        }
+    }
+}
+
+/// A snapshot of the level used by bots to plan.
+struct Arena {
+    floor: HashSet<GridCoords>,
+    /// `Maze` tiles and blockers.
+    walls: Vec<GridCoords>,
+    bombable: Vec<GridCoords>,
+    /// Tiles on the `Hazards` layer, which eliminate players but don't block explosions.
+    hazards: Vec<GridCoords>,
+    bombs: Vec<GridCoords>,
+    /// Tiles which will be caught in an explosion, and the time in seconds until they are.
+    danger: HashMap<GridCoords, f32>,
+    /// The blast range of bombs on this level.
+    blast_range: i32,
+}
+
+impl Arena {
+    fn is_walkable(&self, coords: GridCoords) -> bool {
+        self.floor.contains(&coords)
+            && !self.walls.contains(&coords)
+            && !self.bombable.contains(&coords)
+            && !self.hazards.contains(&coords)
+            && !self.bombs.contains(&coords)
+    }
+
+    /// Whether `coords` will be caught in an explosion within `lookahead_secs`.
+    fn is_dangerous(&self, coords: GridCoords, lookahead_secs: f32) -> bool {
+        self.danger
+            .get(&coords)
+            .is_some_and(|time| *time <= lookahead_secs)
+    }
+
+    /// Whether a bomb placed at `coords` would destroy a bombable tile or catch any `targets`.
+    fn is_worth_bombing(&self, coords: GridCoords, targets: &[GridCoords]) -> bool {
+        blast_tiles(coords, self.blast_range, &self.walls, &self.bombable)
+            .iter()
+            .any(|tile| self.bombable.contains(tile) || targets.contains(tile))
+    }
+
+    /// Whether there is a safe tile close enough to run to after placing a bomb at `coords`.
+    fn can_escape(&self, coords: GridCoords, lookahead_secs: f32) -> bool {
+        let blast = blast_tiles(coords, self.blast_range, &self.walls, &self.bombable);
+        self.find_path(
+            coords,
+            MAX_ESCAPE_STEPS,
+            |_| true,
+            |tile| !blast.contains(&tile) && !self.is_dangerous(tile, lookahead_secs),
+        )
+        .is_some()
+    }
+
+    /// Breadth-first search through walkable tiles for the nearest tile satisfying `goal`, at most
+    /// `max_steps` away. Returns the path to it, not including `start`.
+    fn find_path(
+        &self,
+        start: GridCoords,
+        max_steps: usize,
+        passable: impl Fn(GridCoords) -> bool,
+        goal: impl Fn(GridCoords) -> bool,
+    ) -> Option<VecDeque<GridCoords>> {
+        let mut came_from = HashMap::from([(start, start)]);
+        let mut frontier = VecDeque::from([(start, 0)]);
+
+        while let Some((coords, steps)) = frontier.pop_front() {
+            if goal(coords) {
+                let mut path = VecDeque::new();
+                let mut current = coords;
+                while current != start {
+                    path.push_front(current);
+                    current = came_from[&current];
+                }
+                return Some(path);
+            }
+
+            if steps == max_steps {
+                continue;
+            }
+
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let next = GridCoords::new(coords.x + dx, coords.y + dy);
+                if self.is_walkable(next) && passable(next) && !came_from.contains_key(&next) {
+                    came_from.insert(next, coords);
+                    frontier.push_back((next, steps + 1));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Decide where to walk and whether to place a bomb.
+    fn plan(
+        &self,
+        skill: &BotSkill,
+        coords: GridCoords,
+        can_place: bool,
+        opponents: &[GridCoords],
+        rng: &mut impl Rng,
+    ) -> (VecDeque<GridCoords>, bool) {
+        let lookahead = skill.lookahead_secs;
+
+        if self.is_dangerous(coords, lookahead) {
+            let path = self
+                .find_path(
+                    coords,
+                    usize::MAX,
+                    |_| true,
+                    |tile| !self.is_dangerous(tile, lookahead),
+                )
+                .unwrap_or_default();
+            return (path, false);
+        }
+
+        let mut targets = opponents.to_vec();
+        if skill.chain_trap {
+            // Cut off the escape routes of opponents who are already in danger.
+            targets.extend(
+                opponents
+                    .iter()
+                    .filter(|opponent| self.is_dangerous(**opponent, f32::INFINITY))
+                    .flat_map(|opponent| {
+                        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                            .map(|(dx, dy)| GridCoords::new(opponent.x + dx, opponent.y + dy))
+                    }),
+            );
+        }
+
+        let can_bomb_here = can_place && self.can_escape(coords, lookahead);
+        if can_bomb_here && self.is_worth_bombing(coords, &targets) {
+            return (VecDeque::new(), true);
+        }
+
+        let path = self
+            .find_path(
+                coords,
+                usize::MAX,
+                |tile| !self.is_dangerous(tile, lookahead),
+                |tile| self.is_worth_bombing(tile, &targets) && self.can_escape(tile, lookahead),
+            )
+            .unwrap_or_default();
+
+        // Sometimes place the bomb a tile early.
+        let place_bomb = can_bomb_here && path.len() == 1 && rng.gen_bool(skill.aim_error);
+        (path, place_bomb)
+    }
+}
+
+fn build_arena(
+    tiles: &Query<(&Parent, &GridCoords), Without<Destroyed>>,
+    blockers: &Query<&GridCoords, With<Blocker>>,
+    bombs: &Query<(&Bomb, &Transform)>,
+    ldtk_layer_meta_q: &Query<&LayerMetadata>,
+    blast_range: i32,
+    tile_size: TileSize,
+) -> Arena {
+    let mut arena = Arena {
+        floor: HashSet::new(),
+        walls: blockers.iter().copied().collect(),
+        bombable: Vec::new(),
+        hazards: Vec::new(),
+        bombs: Vec::new(),
+        danger: HashMap::new(),
+        blast_range,
+    };
+
+    for (parent, coords) in tiles.iter() {
+        match ldtk_layer_meta_q
+            .get(**parent)
+            .map(|ldtk_layer| ldtk_layer.identifier.as_str())
+        {
+            Ok("Ground") => {
+                arena.floor.insert(*coords);
+            }
+            Ok("Maze") => arena.walls.push(*coords),
+            Ok("Bombable") => arena.bombable.push(*coords),
+            Ok("Hazards") => arena.hazards.push(*coords),
+            _ => {}
+        }
+    }
+
+    for (bomb, transform) in bombs.iter() {
+        let coords = transform.translation.to_grid(tile_size);
+        arena.bombs.push(coords);
+
+        for tile in blast_tiles(coords, blast_range, &arena.walls, &arena.bombable) {
+            let time = arena.danger.entry(tile).or_insert(f32::INFINITY);
+            *time = time.min(bomb.remaining_secs());
+        }
+    }
+
+    arena
+}
+
+/// Get the movement input to walk towards `target`. This lines up with the target on one axis
+/// before moving along the other, so that bots don't catch on the corners of walls.
+fn steer(position: Vec2, target: Vec2) -> Vec2 {
+    let delta = target - position;
+    if delta.length() <= ARRIVE_DISTANCE {
+        return Vec2::ZERO;
+    }
+
+    let (major, minor) = if delta.x.abs() >= delta.y.abs() {
+        (Vec2::new(delta.x, 0.0), Vec2::new(0.0, delta.y))
+    } else {
+        (Vec2::new(0.0, delta.y), Vec2::new(delta.x, 0.0))
+    };
+
+    if minor.length() > ARRIVE_DISTANCE {
+        minor.normalize()
+    } else {
+        major.normalize_or_zero()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn drive_bots(
+    mut bots: Query<(
+        Entity,
+        &mut Bot,
+        &mut ActionState<PlayerAction>,
+        &Transform,
+        &CountBombs,
+    )>,
+    players: Query<(Entity, &Transform), With<Player>>,
+    tiles: Query<(&Parent, &GridCoords), Without<Destroyed>>,
+    blockers: Query<&GridCoords, With<Blocker>>,
+    bombs: Query<(&Bomb, &Transform)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    rules: Res<LevelRules>,
+    time: Res<Time>,
+    mut rng: ResMut<BotRng>,
+    tile_size: Res<TileSize>,
+) {
+    // Only build the arena if a bot is planning this frame.
+    let mut arena = None;
+
+    for (entity, mut bot, mut action_state, transform, count_bombs) in bots.iter_mut() {
+        // Placing a bomb only needs a single press.
+        action_state.release(&PlayerAction::Bomb);
+
+        let position = transform.translation.truncate();
+        let coords = position.to_grid(*tile_size);
+
+        bot.think_timer.tick(time.delta());
+        if bot.think_timer.just_finished() {
+            let arena = arena.get_or_insert_with(|| {
+                build_arena(
+                    &tiles,
+                    &blockers,
+                    &bombs,
+                    &ldtk_layer_meta_q,
+                    rules.0.blast_range,
+                    *tile_size,
+                )
+            });
+            let opponents = players
+                .iter()
+                .filter(|(player, _)| *player != entity)
+                .map(|(_, transform)| transform.translation.to_grid(*tile_size))
+                .collect::<Vec<_>>();
+
+            let (path, place_bomb) = arena.plan(
+                &bot.skill,
+                coords,
+                count_bombs.can_place(rules.0.max_bombs),
+                &opponents,
+                &mut rng.0,
+            );
+            bot.path = path;
+            if place_bomb {
+                action_state.press(&PlayerAction::Bomb);
+            }
+        }
+
+        while bot.path.front().is_some_and(|next| {
+            position.distance(next.to_world(*tile_size) + spawn_offset(*tile_size))
+                <= ARRIVE_DISTANCE
+        }) {
+            bot.path.pop_front();
+        }
+
+        // With nowhere to go, stay in the middle of the current tile.
+        let target = bot
+            .path
+            .front()
+            .copied()
+            .unwrap_or(coords)
+            .to_world(*tile_size)
+            + spawn_offset(*tile_size);
+        action_state.set_axis_pair(&PlayerAction::Move, steer(position, target));
+    }
+}
@@ -4,7 +4,7 @@ use bevy_ecs_ldtk::prelude::*;
 use bevy_inspector_egui::prelude::*;
 use noise::{NoiseFn, Perlin};
 
-use crate::GameState;
+use crate::{arena::GeneratedArena, ldtk::TileSize, netplay::NetplaySet, GameState};
 
 pub struct CameraPlugin;
 
@@ -19,7 +19,12 @@ impl Plugin for CameraPlugin {
             .add_event::<CameraTrauma>()
             .add_systems(
                 Update,
-                (apply_shake, decay_trauma, apply_trauma).run_if(in_state(GameState::InGame)),
+                (
+                    apply_shake,
+                    decay_trauma,
+                    apply_trauma.after(NetplaySet::Effects),
+                )
+                    .run_if(in_state(GameState::InGame)),
             );
     }
 }
@@ -126,18 +131,31 @@ fn apply_shake(
     }
 }
 
-/// Centers the camera on the LDtk world. Must have a single entity with `LdtkProject` or this system
-/// will panic.
+/// Centers the camera on the selected level. Unless the level is a [`GeneratedArena`], there must
+/// be a single entity with `LdtkProject` or this system will panic.
 fn center_camera(
     mut camera_query: Single<&mut CameraShake, With<Camera>>,
+    arena_query: Query<&GeneratedArena>,
     ldtk_query: Query<&LdtkProjectHandle>,
     ldtk_assets: Res<Assets<LdtkProject>>,
-    // TODO: We may need to access this resource again in future if we have multiple levels.
-    // level: Res<LevelSelection>,
+    level_selection: Res<LevelSelection>,
+    tile_size: Res<TileSize>,
 ) {
+    if let Ok(arena) = arena_query.get_single() {
+        camera_query.center = (arena.size_px(*tile_size) / 2.0).extend(999.9);
+        return;
+    }
+
     // Get coordinates to center the camera on the level
     let ldtk_asset_handle = ldtk_query.single();
-    let ldtk_level = ldtk_assets.get(ldtk_asset_handle).unwrap().root_levels()[0].clone();
+    let Some(ldtk_level) = ldtk_assets
+        .get(ldtk_asset_handle)
+        .unwrap()
+        .find_raw_level_by_level_selection(&level_selection)
+    else {
+        warn!("no level matches {level_selection:?}, can't center the camera");
+        return;
+    };
     let level_dimensions = Vec2::new(ldtk_level.px_wid as f32, ldtk_level.px_hei as f32);
 
     camera_query.center = (level_dimensions / 2.0).extend(999.9);
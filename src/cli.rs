@@ -0,0 +1,271 @@
+//! Command line arguments. These are kept deliberately simple, so we parse them by hand.
+
+use std::{fmt::Display, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use crate::netplay::{NetplayPlayer, MAX_PLAYERS};
+
+const USAGE: &str = "\
+Usage: bomby [OPTIONS]
+
+Options:
+  --simulate <N>   Play N bot-vs-bot matches without a window, and write the results
+  --level <NAME>   The identifier of the LDtk level to simulate, or the path of an ASCII map
+                   [default: the first level]
+  --map <PATH>     Play the ASCII map at this path in the assets folder, such as maps/example.map,
+                   skipping the menus
+  --seed <SEED>    The seed for the gameplay RNG. Every peer in an online game must use the same
+                   seed [default: the `seed` setting, or random when playing locally, otherwise 0]
+  --output <PATH>  Where to write the simulation results, as .json or .csv [default: sim.json]
+  --netplay <PORT> Play online, using this local UDP port
+  --players <LIST> The players in an online game, as a comma-separated list of `local` or the
+                   address of a remote peer. This must be in the same order for every peer
+  --sync-test <N>  Play locally with the netplay code, rolling back N frames every frame to check
+                   that the gameplay is deterministic. Players 2-4 are given random inputs
+  --record <PATH>  Record the local game to a replay file, which is saved after each round
+  --replay <PATH>  Play back a replay file. Space pauses, Right steps forward a frame while paused,
+                   and Up and Down change the speed
+  --snapshot <PATH> Start a local game from a snapshot file. During local play, F5 saves a
+                   snapshot and F9 loads it again, using this file instead of snapshot.json
+  --connect <ADDR> Play on a dedicated server started with `bomby-server`, such as 127.0.0.1:7979
+  -h, --help       Print this message";
+
+const SERVER_USAGE: &str = "\
+Usage: bomby-server [OPTIONS]
+
+Options:
+  --port <PORT>    The UDP port to listen on [default: 7979]
+  --players <N>    The number of players, from 2 to 4. The game starts once this many clients have
+                   joined [default: 2]
+  --level <NAME>   The identifier of the LDtk level to play, or the path of an ASCII map
+                   [default: the first level]
+  --seed <SEED>    The seed for the gameplay RNG [default: the `seed` setting, or random]
+  -h, --help       Print this message";
+
+const LINT_USAGE: &str = "\
+Usage: bomby-lint-level [OPTIONS] [PATH]
+
+Arguments:
+  [PATH]           The LDtk project to check [default: assets/level.ldtk]
+
+Options:
+  --json           Print the problems as JSON, for CI
+  -h, --help       Print this message";
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub simulate: Option<u32>,
+    pub level: Option<String>,
+    pub map: Option<String>,
+    pub seed: Option<u64>,
+    pub output: Option<PathBuf>,
+    pub netplay: Option<u16>,
+    pub players: Vec<NetplayPlayer>,
+    pub sync_test: Option<usize>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub connect: Option<SocketAddr>,
+}
+
+impl Args {
+    /// Parse the command line arguments, printing the usage and exiting on `--help` or an error.
+    pub fn parse() -> Self {
+        Self::try_parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        })
+    }
+
+    fn try_parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for argument: {arg}"))
+            };
+
+            match arg.as_str() {
+                "--simulate" => parsed.simulate = Some(parse_value(&arg, value()?)?),
+                "--level" => parsed.level = Some(value()?),
+                "--map" => parsed.map = Some(value()?),
+                "--seed" => parsed.seed = Some(parse_value(&arg, value()?)?),
+                "--output" => parsed.output = Some(value()?.into()),
+                "--netplay" => parsed.netplay = Some(parse_value(&arg, value()?)?),
+                "--sync-test" => parsed.sync_test = Some(parse_value(&arg, value()?)?),
+                "--record" => parsed.record = Some(value()?.into()),
+                "--replay" => parsed.replay = Some(value()?.into()),
+                "--snapshot" => parsed.snapshot = Some(value()?.into()),
+                "--connect" => parsed.connect = Some(parse_value(&arg, value()?)?),
+                "--players" => {
+                    parsed.players = value()?
+                        .split(',')
+                        .map(|player| parse_value(&arg, player.trim().to_string()))
+                        .collect::<Result<_, _>>()?;
+                }
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unrecognised argument: {arg}")),
+            }
+        }
+
+        if parsed.netplay.is_some() == parsed.players.is_empty() {
+            return Err("--netplay and --players must be used together".to_string());
+        }
+        if parsed.netplay.is_some() && parsed.sync_test.is_some() {
+            return Err("--netplay and --sync-test can't be used together".to_string());
+        }
+        if parsed.replay.is_some()
+            && (parsed.record.is_some() || parsed.netplay.is_some() || parsed.sync_test.is_some())
+        {
+            return Err(
+                "--replay can't be used with --record, --netplay or --sync-test".to_string(),
+            );
+        }
+        if parsed.snapshot.is_some()
+            && (parsed.record.is_some()
+                || parsed.replay.is_some()
+                || parsed.netplay.is_some()
+                || parsed.sync_test.is_some())
+        {
+            return Err(
+                "--snapshot can't be used with --record, --replay, --netplay or --sync-test"
+                    .to_string(),
+            );
+        }
+        if parsed.map.is_some()
+            && (parsed.replay.is_some() || parsed.snapshot.is_some() || parsed.connect.is_some())
+        {
+            return Err("--map can't be used with --replay, --snapshot or --connect".to_string());
+        }
+        if parsed.connect.is_some()
+            && (parsed.record.is_some()
+                || parsed.replay.is_some()
+                || parsed.snapshot.is_some()
+                || parsed.netplay.is_some()
+                || parsed.sync_test.is_some())
+        {
+            return Err(
+                "--connect can't be used with --record, --replay, --snapshot, --netplay or \
+                 --sync-test"
+                    .to_string(),
+            );
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Command line arguments for the `bomby-server` binary.
+#[derive(Debug)]
+pub struct ServerArgs {
+    pub port: Option<u16>,
+    pub players: usize,
+    pub level: Option<String>,
+    pub seed: Option<u64>,
+}
+
+impl Default for ServerArgs {
+    fn default() -> Self {
+        Self {
+            port: None,
+            players: 2,
+            level: None,
+            seed: None,
+        }
+    }
+}
+
+impl ServerArgs {
+    /// Parse the command line arguments, printing the usage and exiting on `--help` or an error.
+    pub fn parse() -> Self {
+        Self::try_parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{e}\n\n{SERVER_USAGE}");
+            std::process::exit(2);
+        })
+    }
+
+    fn try_parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for argument: {arg}"))
+            };
+
+            match arg.as_str() {
+                "--port" => parsed.port = Some(parse_value(&arg, value()?)?),
+                "--players" => parsed.players = parse_value(&arg, value()?)?,
+                "--level" => parsed.level = Some(value()?),
+                "--seed" => parsed.seed = Some(parse_value(&arg, value()?)?),
+                "-h" | "--help" => {
+                    println!("{SERVER_USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unrecognised argument: {arg}")),
+            }
+        }
+
+        if !(2..=MAX_PLAYERS).contains(&parsed.players) {
+            return Err(format!(
+                "--players must be between 2 and {MAX_PLAYERS}, got {}",
+                parsed.players
+            ));
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Command line arguments for the `bomby-lint-level` binary.
+#[derive(Debug)]
+pub struct LintArgs {
+    pub path: PathBuf,
+    pub json: bool,
+}
+
+impl LintArgs {
+    /// Parse the command line arguments, printing the usage and exiting on `--help` or an error.
+    pub fn parse() -> Self {
+        Self::try_parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{e}\n\n{LINT_USAGE}");
+            std::process::exit(2);
+        })
+    }
+
+    fn try_parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut path = None;
+        let mut json = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--json" => json = true,
+                "-h" | "--help" => {
+                    println!("{LINT_USAGE}");
+                    std::process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unrecognised argument: {arg}")),
+                _ if path.is_none() => path = Some(arg.into()),
+                _ => return Err(format!("unexpected argument: {arg}")),
+            }
+        }
+
+        Ok(Self {
+            path: path.unwrap_or_else(|| "assets/level.ldtk".into()),
+            json,
+        })
+    }
+}
+
+fn parse_value<T>(arg: &str, value: String) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid value for argument {arg}: {e}"))
+}
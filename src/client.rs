@@ -0,0 +1,327 @@
+//! Client for the dedicated [`server`](crate::server), started with `--connect <ADDR>`. The client
+//! sends the local player's input to the server every frame, and shows the state of the round that
+//! the server sends back. The gameplay isn't simulated locally, so the client has no say in what
+//! happens.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::{TileTextureIndex, TileVisible};
+use leafwing_input_manager::prelude::*;
+
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use crate::{
+    arena::NextArenaSeed,
+    audio::PlaySfx,
+    bomb::{spawn_bomb, Bomb, BombSprite, Explosion, BOMB_TRAUMA},
+    camera::CameraTrauma,
+    config::Config,
+    door::Door,
+    ldtk::{Destroyed, TileSize, ToGrid},
+    netplay::{encode_input, NetplaySet},
+    player::{input_map, CountPlayers, Player, PlayerAction, PlayerController, Velocity},
+    rules::Ruleset,
+    server::{receive, send, ClientMessage, FrameParts, FrameState, ServerMessage},
+    teleporter::Teleportable,
+    GameState,
+};
+
+pub struct ClientPlugin;
+
+/// How often to ask to join until the server replies.
+const JOIN_INTERVAL_SECS: f32 = 1.0;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, connect.run_if(resource_added::<ClientSettings>))
+            .add_systems(
+                Update,
+                (receive_messages, send_input)
+                    .chain()
+                    .run_if(resource_exists::<ServerConnection>)
+                    .before(NetplaySet::Local),
+            )
+            .add_systems(
+                Update,
+                apply_frame
+                    .after(receive_messages)
+                    .run_if(in_state(GameState::InGame).and(resource_exists::<ServerConnection>)),
+            )
+            .add_systems(
+                Last,
+                leave
+                    .run_if(on_event::<AppExit>)
+                    .run_if(resource_exists::<ServerConnection>),
+            );
+    }
+}
+
+/// The server to connect to, from the command line.
+#[derive(Resource, Debug)]
+pub struct ClientSettings {
+    pub server: SocketAddr,
+}
+
+/// The connection to a dedicated server. While this exists, the gameplay isn't simulated locally.
+#[derive(Resource, Debug)]
+pub struct ServerConnection {
+    socket: UdpSocket,
+    server: SocketAddr,
+    /// The index of our player, once the server has welcomed us.
+    player: Option<usize>,
+    /// The round of the last frame received from the server.
+    round: Option<u32>,
+    join_timer: Timer,
+    /// The parts of a frame which is still arriving from the server.
+    frame_parts: FrameParts,
+    /// The most recent frame received from the server, which hasn't been shown yet.
+    latest: Option<FrameState>,
+}
+
+/// Marker component for the entity which reads the local player's input to send to the server.
+#[derive(Component)]
+struct ClientInput;
+
+fn connect(mut commands: Commands, settings: Res<ClientSettings>, mut exit: EventWriter<AppExit>) {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("failed to open a socket to connect to the server: {e}");
+            exit.send(AppExit::error());
+            return;
+        }
+    };
+
+    // Start the timer finished, so that the first request is sent straight away.
+    let mut join_timer = Timer::from_seconds(JOIN_INTERVAL_SECS, TimerMode::Repeating);
+    join_timer.set_elapsed(join_timer.duration());
+
+    info!("connecting to {}", settings.server);
+    commands.insert_resource(ServerConnection {
+        socket,
+        server: settings.server,
+        player: None,
+        round: None,
+        join_timer,
+        frame_parts: default(),
+        latest: None,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn receive_messages(
+    mut commands: Commands,
+    mut connection: ResMut<ServerConnection>,
+    mut config: ResMut<Config>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut ruleset: ResMut<Ruleset>,
+    mut count_players: ResMut<CountPlayers>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let server = connection.server;
+
+    for (message, addr) in receive::<ServerMessage>(&connection.socket) {
+        if addr != server {
+            debug!("ignoring message from {addr}");
+            continue;
+        }
+
+        match message {
+            ServerMessage::Welcome {
+                player,
+                count_players: num_players,
+                level,
+                ruleset: server_ruleset,
+            } => {
+                if connection.player.is_some() {
+                    continue;
+                }
+                info!(
+                    "joined as player {}, waiting for the game to start",
+                    player + 1
+                );
+
+                connection.player = Some(player);
+                *level_selection = level.to_selection();
+                *ruleset = server_ruleset;
+                config.players = vec![PlayerController::Network; num_players];
+                count_players.0 = num_players;
+
+                commands.spawn((
+                    InputManagerBundle::<PlayerAction> {
+                        input_map: input_map(0),
+                        ..default()
+                    },
+                    ClientInput,
+                    Name::new("Client input"),
+                ));
+            }
+            ServerMessage::Full => {
+                error!("the server at {server} is full");
+                exit.send(AppExit::error());
+            }
+            ServerMessage::FramePart {
+                frame,
+                part,
+                parts,
+                bytes,
+            } => {
+                let Some(mut frame) = connection.frame_parts.add(frame, part, parts, bytes) else {
+                    continue;
+                };
+
+                if connection.round != Some(frame.round) {
+                    // A new round has started, so reload the level. Frames from the new round are
+                    // shown once it has loaded.
+                    connection.round = Some(frame.round);
+                    if let Some(level) = &frame.level {
+                        *level_selection = level.to_selection();
+                    }
+                    if let Some(seed) = frame.arena_seed {
+                        commands.insert_resource(NextArenaSeed(seed));
+                    }
+                    next_state.set(GameState::LoadingLevel);
+                } else {
+                    // Only the latest state is shown, but the explosions of any frames it replaces
+                    // still need to be shown.
+                    if let Some(mut replaced) = connection.latest.take() {
+                        replaced.explosions.append(&mut frame.explosions);
+                        frame.explosions = replaced.explosions;
+                    }
+                    connection.latest = Some(frame);
+                }
+            }
+        }
+    }
+}
+
+/// Ask to join until the server welcomes us, then send our input every frame.
+fn send_input(
+    mut connection: ResMut<ServerConnection>,
+    local_input: Query<&ActionState<PlayerAction>, With<ClientInput>>,
+    time: Res<Time>,
+) {
+    if connection.player.is_none() {
+        if connection.join_timer.tick(time.delta()).just_finished() {
+            send(&connection.socket, &ClientMessage::Join, connection.server);
+        }
+        return;
+    }
+
+    let input = local_input.get_single().map_or(0, encode_input);
+    send(
+        &connection.socket,
+        &ClientMessage::Input(input),
+        connection.server,
+    );
+}
+
+/// Show the latest frame from the server.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn apply_frame(
+    mut commands: Commands,
+    mut connection: ResMut<ServerConnection>,
+    mut players: Query<(Entity, &Player, &mut Transform, &mut Velocity)>,
+    bombs: Query<(Entity, &Transform), (With<Bomb>, Without<Player>)>,
+    bomb_sprite: Res<BombSprite>,
+    mut tiles: Query<(
+        Entity,
+        &Parent,
+        &GridCoords,
+        &mut TileTextureIndex,
+        Has<Destroyed>,
+    )>,
+    mut doors: Query<(&mut Door, &GridCoords, &mut Visibility)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    mut ev_explosion: EventWriter<Explosion>,
+    mut ev_sfx: EventWriter<PlaySfx>,
+    mut ev_trauma: EventWriter<CameraTrauma>,
+    tile_size: Res<TileSize>,
+) {
+    let Some(frame) = connection.latest.take() else {
+        return;
+    };
+
+    for (entity, player, mut transform, mut velocity) in players.iter_mut() {
+        match frame.players.iter().find(|state| state.player == player.0) {
+            Some(state) => {
+                transform.translation = state.translation;
+                *velocity = state.velocity.clone();
+            }
+            None => {
+                commands.entity(entity).despawn_recursive();
+                ev_sfx.send(PlaySfx::PlayerDeath);
+            }
+        }
+    }
+
+    // Bombs are respawned each frame, rather than matched up with the server's bombs.
+    let old_bombs: HashSet<GridCoords> = bombs
+        .iter()
+        .map(|(_, transform)| transform.translation.to_grid(*tile_size))
+        .collect();
+    for (entity, _) in bombs.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for state in frame.bombs {
+        let coords = state.translation.to_grid(*tile_size);
+        if !old_bombs.contains(&coords) {
+            ev_sfx.send(PlaySfx::BombFuse);
+        }
+        spawn_bomb(
+            &mut commands,
+            &bomb_sprite,
+            state.translation,
+            state.bomb,
+            Teleportable::new(coords),
+            *tile_size,
+        );
+    }
+
+    for (entity, _, coords, mut texture_index, destroyed) in
+        tiles.iter_mut().filter(|(_, parent, ..)| {
+            ldtk_layer_meta_q
+                .get(***parent)
+                .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+        })
+    {
+        if !destroyed && frame.destroyed_tiles.iter().any(|c| c.0 == *coords) {
+            commands
+                .entity(entity)
+                .insert((Destroyed, TileVisible(false)));
+        } else if let Some((_, index)) = frame.armoured_tiles.iter().find(|(c, _)| c.0 == *coords) {
+            texture_index.0 = *index;
+        }
+    }
+
+    for (mut door, coords, mut visibility) in doors.iter_mut() {
+        let Some((_, open)) = frame.doors.iter().find(|(c, _)| c.0 == *coords) else {
+            continue;
+        };
+        if door.open != *open {
+            door.open = *open;
+            *visibility = if *open {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            };
+        }
+    }
+
+    for explosion in frame.explosions.iter() {
+        ev_explosion.send(explosion.into());
+        ev_sfx.send(PlaySfx::BombExplosion);
+        ev_trauma.send(CameraTrauma(BOMB_TRAUMA));
+    }
+}
+
+/// Tell the server we are leaving, so that it doesn't have to wait for us to time out.
+fn leave(connection: Res<ServerConnection>) {
+    send(&connection.socket, &ClientMessage::Leave, connection.server);
+}
@@ -3,8 +3,8 @@
 //!
 //! Linux: `~/.config/bomby/config.toml`
 //!
-//! Currently, the config is loaded at startup and cannot be changed from inside the game. So, this
-//! module does not export a bevy plugin (yet).
+//! Currently, the config is loaded at startup and only the player slots can be changed from inside
+//! the game, without being saved. So, this module does not export a bevy plugin (yet).
 
 use bevy::prelude::*;
 
@@ -13,6 +13,8 @@ use serde_derive::{Deserialize, Serialize};
 
 use std::fs;
 
+use crate::{bot::BotDifficulty, player::PlayerController, rules::Ruleset};
+
 const DEFAULT_ASPECT_RATIO: f32 = 16.0 / 9.0;
 const DEFAULT_WINDOW_HEIGHT: f32 = 900.0;
 const DEFAULT_WINDOW_WIDTH: f32 = DEFAULT_WINDOW_HEIGHT * DEFAULT_ASPECT_RATIO;
@@ -26,6 +28,15 @@ pub struct Config {
     pub window_height: f32,
     pub bgm_volume: f64,
     pub sfx_volume: f64,
+    pub ruleset: Ruleset,
+    /// Who controls each player slot, in order. Missing slots are controlled by humans.
+    pub players: Vec<PlayerController>,
+    /// The difficulty of each player slot when it is controlled by a bot, in order. Missing slots
+    /// are `Normal`.
+    pub bot_difficulty: Vec<BotDifficulty>,
+    /// The seed for the gameplay RNG, so that games can be reproduced. This is random if not set,
+    /// and can be overridden with `--seed`.
+    pub seed: Option<u64>,
 }
 
 impl Default for Config {
@@ -36,6 +47,11 @@ impl Default for Config {
             window_height: DEFAULT_WINDOW_HEIGHT,
             bgm_volume: 1.0,
             sfx_volume: 1.0,
+            ruleset: Ruleset::default(),
+            // Bots can be chosen here or with the player slot buttons in the main menu.
+            players: vec![PlayerController::Human; 4],
+            bot_difficulty: vec![BotDifficulty::Normal; 4],
+            seed: None,
         }
     }
 }
@@ -70,6 +86,7 @@ pub fn load_config() -> Config {
     // Ensure sensible bounds.
     config.bgm_volume = config.bgm_volume.clamp(0.0, 1.0);
     config.sfx_volume = config.sfx_volume.clamp(0.0, 1.0);
+    config.ruleset = config.ruleset.validated();
 
     config
 }
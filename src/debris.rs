@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::{
+    bomb::Explosion,
+    ldtk::{TileSize, ToWorld},
+    netplay::NetplaySet,
+    z_sort::{ZSort, PLAYER_Z},
+    CosmeticRng, GameState,
+};
+
+pub struct DebrisPlugin;
+
+impl Plugin for DebrisPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                (spawn_debris, spawn_scorch_marks).after(NetplaySet::Effects),
+                update_debris,
+            )
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), despawn_effects);
+    }
+}
+
+/// The number of fragments spawned for each destroyed tile.
+const DEBRIS_PER_TILE: usize = 6;
+const DEBRIS_LIFETIME_SECS: f32 = 0.6;
+const DEBRIS_SPEED: f32 = 120.0;
+/// The fraction of its speed that debris loses per second.
+const DEBRIS_DRAG: f32 = 0.9;
+const DEBRIS_COLOR: Color = Color::srgb(0.45, 0.3, 0.2);
+
+/// Z value for scorch marks, between the `Ground` layer and the `Maze` layer.
+const SCORCH_Z: f32 = 0.5;
+const SCORCH_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.3);
+
+/// A short-lived fragment of a destroyed tile.
+#[derive(Component, Debug)]
+struct Debris {
+    velocity: Vec2,
+    lifetime: Timer,
+}
+
+/// A scorch mark left on the ground by an explosion. These last until the end of the round.
+#[derive(Component, Debug)]
+struct ScorchMark;
+
+fn spawn_debris(
+    mut commands: Commands,
+    mut ev_explosion: EventReader<Explosion>,
+    mut rng: ResMut<CosmeticRng>,
+    tile_size: Res<TileSize>,
+) {
+    for coords in ev_explosion
+        .read()
+        .flat_map(|explosion| explosion.destroyed_tiles.iter())
+    {
+        let origin = coords.to_world(*tile_size);
+        for _ in 0..DEBRIS_PER_TILE {
+            let angle = rng.0.gen_range(0.0..std::f32::consts::TAU);
+            let speed = rng.0.gen_range(0.5..1.0) * DEBRIS_SPEED;
+            let size = rng.0.gen_range(2.0..6.0);
+
+            commands.spawn((
+                Sprite::from_color(DEBRIS_COLOR, Vec2::splat(size)),
+                Transform::from_translation(origin.extend(PLAYER_Z)),
+                ZSort(PLAYER_Z),
+                Debris {
+                    velocity: Vec2::from_angle(angle) * speed,
+                    lifetime: Timer::from_seconds(DEBRIS_LIFETIME_SECS, TimerMode::Once),
+                },
+                Name::new("Debris"),
+            ));
+        }
+    }
+}
+
+fn spawn_scorch_marks(
+    mut commands: Commands,
+    mut ev_explosion: EventReader<Explosion>,
+    scorch_marks: Query<&Transform, With<ScorchMark>>,
+    tile_size: Res<TileSize>,
+) {
+    let mut scorched = scorch_marks
+        .iter()
+        .map(|transform| transform.translation.truncate())
+        .collect::<Vec<_>>();
+
+    for translation in ev_explosion
+        .read()
+        .flat_map(|explosion| explosion.tiles.iter())
+        .map(|coords| coords.to_world(*tile_size))
+    {
+        // Scorch marks are translucent, so avoid stacking them on the same tile.
+        if scorched.contains(&translation) {
+            continue;
+        }
+        scorched.push(translation);
+
+        commands.spawn((
+            Sprite::from_color(SCORCH_COLOR, Vec2::splat(tile_size.0)),
+            Transform::from_translation(translation.extend(SCORCH_Z)),
+            ScorchMark,
+            Name::new("Scorch mark"),
+        ));
+    }
+}
+
+/// Move debris, slowing it down and fading it out over its lifetime.
+fn update_debris(
+    mut commands: Commands,
+    mut debris: Query<(Entity, &mut Debris, &mut Transform, &mut Sprite)>,
+    time: Res<Time>,
+) {
+    for (entity, mut debris, mut transform, mut sprite) in debris.iter_mut() {
+        debris.lifetime.tick(time.delta());
+        if debris.lifetime.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        debris.velocity *= (1.0 - DEBRIS_DRAG * time.delta_secs()).max(0.0);
+        transform.translation += (debris.velocity * time.delta_secs()).extend(0.0);
+        sprite.color.set_alpha(1.0 - debris.lifetime.fraction());
+    }
+}
+
+fn despawn_effects(
+    mut commands: Commands,
+    to_despawn: Query<Entity, Or<(With<Debris>, With<ScorchMark>)>>,
+) {
+    to_despawn
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    bomb::{BombSet, Explosion},
+    ldtk::{Blocker, TileSize, ToGrid},
+    netplay::Gameplay,
+    player::Player,
+    GameState,
+};
+
+pub struct DoorPlugin;
+
+impl Plugin for DoorPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity::<SwitchBundle>("Switch")
+            .register_ldtk_entity::<DoorBundle>("Door")
+            .add_event::<ToggleDoors>()
+            .add_systems(
+                Gameplay,
+                (press_switches, explode_switches, toggle_doors, update_doors)
+                    .chain()
+                    .after(BombSet)
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// A pressure plate/switch placed in LDtk. Stepping on it or hitting it with an explosion toggles
+/// every door in its `Doors` entity reference array field.
+#[derive(Component, Default, Debug, Clone)]
+pub struct Switch {
+    doors: Vec<EntityIid>,
+    /// Whether a player was standing on the switch last frame. Used so that only stepping onto the
+    /// switch toggles it, rather than standing on it.
+    pub pressed: bool,
+}
+
+impl From<&EntityInstance> for Switch {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        let doors = entity_instance
+            .get_maybe_entity_refs_field("Doors")
+            .unwrap_or_else(|e| {
+                warn!("invalid doors for switch {}: {e}", entity_instance.iid);
+                &[]
+            })
+            .iter()
+            .flatten()
+            .map(|entity_ref| EntityIid::new(entity_ref.entity_iid.clone()))
+            .collect();
+
+        Self {
+            doors,
+            pressed: false,
+        }
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+struct SwitchBundle {
+    #[from_entity_instance]
+    switch: Switch,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[sprite_sheet]
+    sprite: Sprite,
+}
+
+/// A door placed in LDtk. When closed it is a [`Blocker`], and when open it is hidden and can be
+/// walked through. The initial state is set with the `Open` bool field.
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Door {
+    pub open: bool,
+}
+
+impl From<&EntityInstance> for Door {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        Self {
+            open: *entity_instance.get_bool_field("Open").unwrap_or(&false),
+        }
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+struct DoorBundle {
+    #[from_entity_instance]
+    door: Door,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[sprite_sheet]
+    sprite: Sprite,
+}
+
+/// Event to toggle the doors linked to a [`Switch`] entity.
+#[derive(Event, Debug)]
+struct ToggleDoors(Entity);
+
+/// Toggle switches when a player steps onto them.
+fn press_switches(
+    mut switches: Query<(Entity, &mut Switch, &GridCoords)>,
+    players: Query<&Transform, With<Player>>,
+    mut ev_toggle: EventWriter<ToggleDoors>,
+    tile_size: Res<TileSize>,
+) {
+    for (entity, mut switch, coords) in switches.iter_mut() {
+        let pressed = players
+            .iter()
+            .any(|transform| transform.translation.to_grid(*tile_size) == *coords);
+
+        if pressed && !switch.pressed {
+            ev_toggle.send(ToggleDoors(entity));
+        }
+        switch.pressed = pressed;
+    }
+}
+
+/// Toggle switches caught in an explosion.
+fn explode_switches(
+    switches: Query<(Entity, &GridCoords), With<Switch>>,
+    mut ev_explosion: EventReader<Explosion>,
+    mut ev_toggle: EventWriter<ToggleDoors>,
+) {
+    for explosion in ev_explosion.read() {
+        for (entity, _) in switches
+            .iter()
+            .filter(|(_, coords)| explosion.tiles.contains(coords))
+        {
+            ev_toggle.send(ToggleDoors(entity));
+        }
+    }
+}
+
+fn toggle_doors(
+    switches: Query<&Switch>,
+    mut doors: Query<(&EntityIid, &mut Door)>,
+    mut ev_toggle: EventReader<ToggleDoors>,
+) {
+    for ToggleDoors(switch) in ev_toggle.read() {
+        let Ok(switch) = switches.get(*switch) else {
+            continue;
+        };
+
+        for (_, mut door) in doors
+            .iter_mut()
+            .filter(|(iid, _)| switch.doors.contains(iid))
+        {
+            door.open = !door.open;
+        }
+    }
+}
+
+/// Keep the [`Blocker`] component and visibility of doors in sync with their state. This also
+/// runs for newly spawned doors, as `Changed` includes `Added`.
+fn update_doors(
+    mut commands: Commands,
+    mut doors: Query<(Entity, &Door, &mut Visibility), Changed<Door>>,
+) {
+    for (entity, door, mut visibility) in doors.iter_mut() {
+        if door.open {
+            *visibility = Visibility::Hidden;
+            commands.entity(entity).remove::<Blocker>();
+        } else {
+            *visibility = Visibility::Inherited;
+            commands.entity(entity).insert(Blocker);
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use bevy::prelude::*;
-use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_ldtk::{ldtk::Level, prelude::*};
+use serde_derive::{Deserialize, Serialize};
 
-use crate::GameState;
+use crate::{
+    arena::generated_level_selected, ascii_map::ascii_map_selected, netplay::gameplay_is_local,
+    GameState,
+};
 
 pub struct BombyLdtkPlugin;
 
@@ -9,68 +13,203 @@ impl Plugin for BombyLdtkPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(LdtkPlugin)
             .insert_resource(LevelSelection::index(0))
-            .add_systems(OnEnter(GameState::LoadingLevel), setup)
+            .init_resource::<TileSize>()
+            // Every level is spawned at the origin, as the gameplay assumes that the level starts
+            // at (0, 0), and only the selected level is spawned.
+            .insert_resource(LdtkSettings {
+                level_spawn_behavior: LevelSpawnBehavior::UseZeroTranslation,
+                ..default()
+            })
+            .add_systems(PreStartup, load_project)
+            .add_systems(
+                OnEnter(GameState::LoadingLevel),
+                setup.run_if(not(generated_level_selected).and(not(ascii_map_selected))),
+            )
+            .add_systems(OnExit(GameState::InGame), despawn_world)
             .add_systems(
                 Update,
                 finish_loading.run_if(in_state(GameState::LoadingLevel).and(level_spawned)),
+            )
+            .add_systems(
+                Update,
+                reload_level.run_if(
+                    in_state(GameState::InGame)
+                        .and(on_event::<AssetEvent<LdtkProject>>)
+                        .and(gameplay_is_local)
+                        .and(not(generated_level_selected))
+                        .and(not(ascii_map_selected)),
+                ),
             );
     }
 }
 
-pub const TILE_SIZE_PX: f32 = 32.0;
-pub const TILE_SIZE_PX_INV: f32 = 1.0 / TILE_SIZE_PX;
+/// Marker component for non-tile entities with `GridCoords` that block movement and explosions in
+/// the same way as a tile on the `Maze` layer, such as closed doors.
+#[derive(Component, Default, Debug, Clone, Copy)]
+pub struct Blocker;
+
+/// Marker component for a bombable tile destroyed by an explosion. These tiles are hidden rather
+/// than despawned, so that they can be restored by a rollback during netplay.
+#[derive(Component, Default, Debug, Clone, Copy)]
+pub struct Destroyed;
+
+/// Serde definition for `GridCoords`, for use with `#[serde(with = "GridCoordsDef")]`.
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "GridCoords")]
+pub struct GridCoordsDef {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A level to load, as the parts of a `LevelSelection` that can be saved to a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LevelId {
+    Index(usize),
+    Identifier(String),
+}
+
+impl LevelId {
+    pub fn from_selection(selection: &LevelSelection) -> Self {
+        match selection {
+            LevelSelection::Identifier(identifier) => Self::Identifier(identifier.clone()),
+            LevelSelection::Indices(indices) => Self::Index(indices.level),
+            _ => {
+                warn!("can't save level selection {selection:?}, using the first level");
+                Self::Index(0)
+            }
+        }
+    }
+
+    pub fn to_selection(&self) -> LevelSelection {
+        match self {
+            Self::Index(index) => LevelSelection::index(*index),
+            Self::Identifier(identifier) => LevelSelection::Identifier(identifier.clone()),
+        }
+    }
+}
+
+/// Resource holding the handle to `level.ldtk`, which is loaded at startup so that the levels can
+/// be listed before one is played.
+#[derive(Resource, Debug, Clone)]
+pub struct LdtkProjectAsset(pub Handle<LdtkProject>);
+
+/// The name and size of a level in the LDtk project, for listing in the level select screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelSummary {
+    pub identifier: String,
+    /// The size of the level in tiles.
+    pub width: i32,
+    pub height: i32,
+}
+
+/// List every level in the LDtk project, in the order they appear in LDtk. Levels which can't be
+/// played because their layers have different grid sizes are left out.
+pub fn level_summaries(project: &LdtkProject) -> Vec<LevelSummary> {
+    project
+        .root_levels()
+        .iter()
+        .filter_map(|level| {
+            let grid_size = level_grid_size(level).ok()?;
+            Some(LevelSummary {
+                identifier: level.identifier.clone(),
+                width: level.px_wid / grid_size,
+                height: level.px_hei / grid_size,
+            })
+        })
+        .collect()
+}
+
+/// Resource containing the size of a tile in pixels for the level being played. This is set from the
+/// grid size of the level's layers when it loads, and is needed by [`ToWorld`] and [`ToGrid`].
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct TileSize(pub f32);
+
+impl Default for TileSize {
+    /// The size of the tiles in the tilesets, which generated arenas and ASCII maps are made of.
+    fn default() -> Self {
+        Self(32.0)
+    }
+}
+
+impl TileSize {
+    /// How many times larger the tiles are than the default, which the sprites and the player and
+    /// bomb offsets are drawn for.
+    pub fn scale(self) -> f32 {
+        self.0 / Self::default().0
+    }
+}
+
+/// The grid size in pixels shared by every layer of a level. Returns an error if the layers have
+/// different grid sizes, as the gameplay assumes a single grid, or if the grid size isn't positive.
+pub fn level_grid_size(level: &Level) -> Result<i32, String> {
+    let mut sizes = level
+        .layer_instances
+        .iter()
+        .flatten()
+        .map(|layer| (layer.identifier.as_str(), layer.grid_size));
+
+    let Some((first_layer, grid_size)) = sizes.next() else {
+        return Err(format!("level {} has no layers", level.identifier));
+    };
+    if let Some((layer, other_size)) = sizes.find(|(_, size)| *size != grid_size) {
+        return Err(format!(
+            "level {} has layers with different grid sizes: {first_layer} is {grid_size}px, but \
+             {layer} is {other_size}px",
+            level.identifier
+        ));
+    }
+    if grid_size <= 0 {
+        return Err(format!(
+            "level {} has a grid size of {grid_size}px",
+            level.identifier
+        ));
+    }
+    Ok(grid_size)
+}
 
 pub trait ToWorld {
     /// Convert the LDtk grid coordinates into bevy world coordinates
-    fn to_world(&self) -> Vec2;
+    fn to_world(&self, tile_size: TileSize) -> Vec2;
 }
 
 impl ToWorld for GridCoords {
-    fn to_world(&self) -> Vec2 {
-        // NOTE: It would be good to be able to query the tile size at startup or compile time and
-        // use it here, instead of hardcoding.
+    fn to_world(&self, TileSize(tile_size): TileSize) -> Vec2 {
         Vec2::new(
-            self.x as f32 * TILE_SIZE_PX + TILE_SIZE_PX / 2.0,
-            self.y as f32 * TILE_SIZE_PX + TILE_SIZE_PX / 2.0,
+            self.x as f32 * tile_size + tile_size / 2.0,
+            self.y as f32 * tile_size + tile_size / 2.0,
         )
     }
 }
 
 pub trait ToGrid {
     /// Convert the bevy world coordinates into LDtk grid coordinates
-    fn to_grid(&self) -> GridCoords;
+    fn to_grid(&self, tile_size: TileSize) -> GridCoords;
 }
 
 impl ToGrid for Vec3 {
-    fn to_grid(&self) -> GridCoords {
-        GridCoords::new(
-            (self.x * TILE_SIZE_PX_INV) as i32,
-            (self.y * TILE_SIZE_PX_INV) as i32,
-        )
+    fn to_grid(&self, tile_size: TileSize) -> GridCoords {
+        self.truncate().to_grid(tile_size)
     }
 }
 
 impl ToGrid for Vec2 {
-    fn to_grid(&self) -> GridCoords {
-        GridCoords::new(
-            (self.x * TILE_SIZE_PX_INV) as i32,
-            (self.y * TILE_SIZE_PX_INV) as i32,
-        )
+    fn to_grid(&self, TileSize(tile_size): TileSize) -> GridCoords {
+        GridCoords::new((self.x / tile_size) as i32, (self.y / tile_size) as i32)
     }
 }
 
 pub trait GridNormalise {
     /// Take some coordinate system and normalise it based on `GridCoords`, such that the new value
     /// is the equivalent world coordinate centered on its `GridCoords`.
-    fn grid_normalised(&self) -> Vec2;
+    fn grid_normalised(&self, tile_size: TileSize) -> Vec2;
 }
 
 impl<T> GridNormalise for T
 where
     T: ToGrid,
 {
-    fn grid_normalised(&self) -> Vec2 {
-        self.to_grid().to_world()
+    fn grid_normalised(&self, tile_size: TileSize) -> Vec2 {
+        self.to_grid(tile_size).to_world(tile_size)
     }
 }
 
@@ -82,16 +221,70 @@ pub fn level_spawned(mut level_events: EventReader<LevelEvent>) -> bool {
         .any(|e| matches!(e, LevelEvent::Spawned(_)))
 }
 
-fn finish_loading(mut next_state: ResMut<NextState<GameState>>) {
-    next_state.set(GameState::InGame);
+/// Read the tile size from the level that has just spawned, and start the round. Levels without a
+/// single, positive grid size can't be played, so go back to the menu instead.
+fn finish_loading(
+    mut commands: Commands,
+    worlds: Query<Entity, With<LdtkProjectHandle>>,
+    project: Res<LdtkProjectAsset>,
+    ldtk_assets: Res<Assets<LdtkProject>>,
+    level_selection: Res<LevelSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let grid_size = ldtk_assets
+        .get(&project.0)
+        .and_then(|project| project.find_raw_level_by_level_selection(&level_selection))
+        .ok_or_else(|| format!("no level matches {level_selection:?}"))
+        .and_then(level_grid_size);
+
+    match grid_size {
+        Ok(grid_size) => {
+            commands.insert_resource(TileSize(grid_size as f32));
+            next_state.set(GameState::InGame);
+        }
+        Err(e) => {
+            error!("can't play level: {e}");
+            worlds
+                .iter()
+                .for_each(|world| commands.entity(world).despawn_recursive());
+            next_state.set(GameState::MainMenu);
+        }
+    }
+}
+
+fn load_project(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(LdtkProjectAsset(asset_server.load("level.ldtk")));
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup(mut commands: Commands, project: Res<LdtkProjectAsset>) {
     commands.spawn((
         LdtkWorldBundle {
-            ldtk_handle: asset_server.load("level.ldtk").into(),
+            ldtk_handle: project.0.clone().into(),
             ..default()
         },
         Name::new("LDtkWorld"),
     ));
 }
+
+/// Restart the round when `level.ldtk` is saved while it is being played, so that changes made in
+/// LDtk can be tried straight away. This needs the `hot_reload` feature, and is skipped during
+/// netplay, as the other peers wouldn't reload at the same time, and for generated arenas and ASCII
+/// maps, which don't come from `level.ldtk`.
+fn reload_level(
+    mut ev_asset: EventReader<AssetEvent<LdtkProject>>,
+    project: Res<LdtkProjectAsset>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if ev_asset.read().any(|event| event.is_modified(&project.0)) {
+        info!("level.ldtk has changed, reloading the level");
+        next_state.set(GameState::LoadingLevel);
+    }
+}
+
+/// Despawn the LDtk world at the end of a round, so that the level is reset when the next round
+/// is loaded.
+fn despawn_world(mut commands: Commands, worlds: Query<Entity, With<LdtkProjectHandle>>) {
+    worlds
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
@@ -0,0 +1,96 @@
+#![warn(clippy::semicolon_if_nothing_returned, clippy::uninlined_format_args)]
+
+use bevy::prelude::*;
+
+use rand::{rngs::SmallRng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use serde_derive::{Deserialize, Serialize};
+
+pub mod arena;
+pub mod armour;
+pub mod ascii_map;
+pub mod audio;
+pub mod bomb;
+pub mod bot;
+pub mod camera;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod debris;
+pub mod debug;
+pub mod door;
+pub mod ldtk;
+pub mod lint;
+pub mod lobby;
+pub mod netplay;
+pub mod player;
+pub mod playlist;
+pub mod replay;
+pub mod round;
+pub mod rules;
+pub mod server;
+pub mod sim;
+pub mod snapshot;
+pub mod sync_test;
+pub mod teleporter;
+pub mod tide;
+pub mod ui;
+pub mod z_sort;
+
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// For some reason, the first `StateTransition` schedule seems to happen before `PreStartup`.
+    /// So, in order to initialise resources to be used in `MainMenu`, we have to initialise the
+    /// state with the dummy variant `PreLoad`, which is immediately transitioned to `MainMenu` in
+    /// the first `Startup` schedule. This seems to me like a scheduling bug in bevy, but I haven't
+    /// opened an issue yet.
+    #[default]
+    PreLoad,
+    MainMenu,
+    /// Choosing which level of the LDtk project to play.
+    LevelSelect,
+    /// Hosting or looking for a LAN game, depending on the [`LobbyRole`](lobby::LobbyRole).
+    Lobby,
+    LoadingLevel,
+    InGame,
+}
+
+pub fn go_to_menu(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::MainMenu);
+}
+
+/// Start playing the selected level straight away, skipping the menus.
+pub fn start_level(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::LoadingLevel);
+}
+
+/// RNG for everything that can affect the outcome of a game, such as the generated arena and the
+/// next level in the playlist. This must only be used
+/// in a deterministic order, so that a game can be reproduced from the seed and the inputs.
+///
+/// This is the same generator that `SmallRng` uses on 64-bit platforms, but its state can be saved,
+/// such as in a [`Snapshot`](snapshot::Snapshot).
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct GameRng(Xoshiro256PlusPlus);
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self(Xoshiro256PlusPlus::seed_from_u64(seed))
+    }
+}
+
+/// RNG for cosmetic effects and audio. This is kept separate from the [`GameRng`], so that these
+/// can use it as much as they like without changing the outcome of a game.
+#[derive(Resource)]
+pub struct CosmeticRng(SmallRng);
+
+impl CosmeticRng {
+    /// Create the cosmetic RNG for a game whose [`GameRng`] has the seed `seed`.
+    pub fn from_seed(seed: u64) -> Self {
+        Self(SmallRng::seed_from_u64(seed ^ COSMETIC_SEED_SALT))
+    }
+}
+
+/// Mixed into the seed for the [`CosmeticRng`], so that it doesn't produce the same numbers as the
+/// [`GameRng`].
+const COSMETIC_SEED_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
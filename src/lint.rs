@@ -0,0 +1,199 @@
+//! Checks for LDtk projects, run with the `bomby-lint-level` binary. The project is read straight
+//! from the JSON, without starting the game, so that broken levels can be caught in CI rather than
+//! by a panic when they are played. Each level is checked for:
+//!
+//! - a `Player_1` to `Player_4` spawn point, as `spawn_players` panics without them;
+//! - spawn points on top of a `Maze`, `Bombable` or `Hazards` tile;
+//! - spawn points that can't reach each other once every bombable tile has been destroyed, without
+//!   crossing a hazard;
+//! - layers with different grid sizes, as a level can only have one tile size.
+//!
+//! Tilesets are also checked to exist next to the project.
+//!
+//! Doors and teleporters are ignored when checking whether spawn points can reach each other, as
+//! doors can be opened and teleporters only help.
+
+use bevy::math::IVec2;
+use bevy_ecs_ldtk::ldtk::{LayerInstance, LdtkJson, Level};
+use serde_derive::Serialize;
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, fs,
+    path::Path,
+};
+
+use crate::{ldtk::level_grid_size, netplay::MAX_PLAYERS};
+
+/// Something wrong with an LDtk project.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    /// The identifier of the level, or `None` for a problem with the whole project.
+    pub level: Option<String>,
+    pub check: Check,
+    pub message: String,
+}
+
+/// The check that found a [`Problem`], for filtering the machine-readable output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    MissingSpawn,
+    SpawnInWall,
+    UnreachableSpawn,
+    GridSize,
+    MissingTileset,
+    /// Levels saved in separate files aren't supported.
+    ExternalLevels,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.level {
+            Some(level) => write!(f, "{level}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Load the LDtk project at `path` and check every level.
+pub fn lint_file(path: &Path) -> Result<Vec<Problem>, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("failed to read {path:?}: {e}"))?;
+    let project: LdtkJson =
+        serde_json::from_str(&json).map_err(|e| format!("failed to parse {path:?}: {e}"))?;
+
+    let mut problems = Vec::new();
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for tileset in project.defs.tilesets.iter() {
+        let Some(rel_path) = &tileset.rel_path else {
+            continue;
+        };
+        if !dir.join(rel_path).is_file() {
+            problems.push(Problem {
+                level: None,
+                check: Check::MissingTileset,
+                message: format!(
+                    "tileset {} uses {rel_path:?}, which doesn't exist",
+                    tileset.identifier
+                ),
+            });
+        }
+    }
+
+    if project.external_levels {
+        problems.push(Problem {
+            level: None,
+            check: Check::ExternalLevels,
+            message: "levels are saved in separate files, which aren't supported".to_string(),
+        });
+    }
+
+    for level in project.levels.iter() {
+        problems.extend(lint_level(level));
+    }
+
+    Ok(problems)
+}
+
+/// Check a single level.
+pub fn lint_level(level: &Level) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut problem = |check, message| {
+        problems.push(Problem {
+            level: Some(level.identifier.clone()),
+            check,
+            message,
+        });
+    };
+
+    let layers = level.layer_instances.as_deref().unwrap_or_default();
+
+    let grid_size = match level_grid_size(level) {
+        Ok(grid_size) => grid_size,
+        Err(e) => {
+            problem(Check::GridSize, e);
+            return problems;
+        }
+    };
+
+    let walls = layer_tiles(layers, "Maze");
+    let bombable = layer_tiles(layers, "Bombable");
+    let hazards = layer_tiles(layers, "Hazards");
+
+    let mut spawns = Vec::new();
+    for i in 0..MAX_PLAYERS {
+        let name = format!("Player_{}", i + 1);
+        let Some(spawn) = layers
+            .iter()
+            .flat_map(|layer| layer.entity_instances.iter())
+            .find(|entity| entity.identifier == name)
+        else {
+            problem(Check::MissingSpawn, format!("missing spawn point {name}"));
+            continue;
+        };
+
+        let coords = spawn.grid;
+        if walls.contains(&coords) || bombable.contains(&coords) {
+            problem(
+                Check::SpawnInWall,
+                format!("{name} at {coords} is inside a wall or bombable tile"),
+            );
+        } else if hazards.contains(&coords) {
+            problem(
+                Check::SpawnInWall,
+                format!("{name} at {coords} is on a hazard"),
+            );
+        }
+        spawns.push((name, coords));
+    }
+
+    let size = IVec2::new(level.px_wid / grid_size, level.px_hei / grid_size);
+    if let Some(((first_name, first), rest)) = spawns.split_first() {
+        let impassable = walls.union(&hazards).copied().collect();
+        let reachable = reachable_tiles(*first, &impassable, size);
+        for (name, coords) in rest {
+            if !reachable.contains(coords) {
+                problem(
+                    Check::UnreachableSpawn,
+                    format!("{name} at {coords} can't reach {first_name} at {first}"),
+                );
+            }
+        }
+    }
+
+    problems
+}
+
+/// The grid coordinates of every tile on the layer with this identifier, with the origin in the top
+/// left as in LDtk.
+fn layer_tiles(layers: &[LayerInstance], identifier: &str) -> HashSet<IVec2> {
+    layers
+        .iter()
+        .filter(|layer| layer.identifier == identifier)
+        .flat_map(|layer| {
+            layer
+                .grid_tiles
+                .iter()
+                .chain(layer.auto_layer_tiles.iter())
+                .map(move |tile| tile.px / layer.grid_size.max(1))
+        })
+        .collect()
+}
+
+/// Flood fill the level from `start`, walking through anything but `walls`.
+fn reachable_tiles(start: IVec2, walls: &HashSet<IVec2>, size: IVec2) -> HashSet<IVec2> {
+    let mut reachable = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(coords) = queue.pop_front() {
+        for next in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y].map(|dir| coords + dir) {
+            let in_bounds = next.cmpge(IVec2::ZERO).all() && next.cmplt(size).all();
+            if in_bounds && !walls.contains(&next) && reachable.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reachable
+}
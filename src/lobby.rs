@@ -0,0 +1,593 @@
+//! LAN lobbies for netplay. A host announces its game over UDP broadcast every second, and clients
+//! listening on [`DISCOVERY_PORT`] list the games they hear about in the lobby screen. When the host
+//! starts the game, it sends every client the list of players, so that everyone starts a netplay
+//! session with the same players, seed and ruleset. The host keeps sending this until every client
+//! has acknowledged it, and each client waits until the host has stopped sending it before leaving
+//! the lobby, so that a lost message can't leave anyone behind.
+//!
+//! Announcements are broadcast on the loopback network as well as the LAN, so that several
+//! instances on one machine can find each other for testing.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use crate::{
+    ldtk::LevelId,
+    netplay::{NetplayMode, NetplayPlayer, NetplaySettings, MAX_PLAYERS},
+    rules::Ruleset,
+    GameRng, GameState,
+};
+
+pub struct LobbyPlugin;
+
+/// The port clients listen on for announcements.
+pub const DISCOVERY_PORT: u16 = 7878;
+
+/// How often a host announces its game, and a client which has joined a game repeats its request
+/// in case it was lost.
+const ANNOUNCE_INTERVAL_SECS: f32 = 1.0;
+
+/// How long a game stays listed after its last announcement.
+const GAME_TIMEOUT_SECS: f32 = 3.0;
+
+/// How long a client waits after the host last sent it the players before starting the game. The
+/// host sends them every frame until it has heard back, so this is plenty for a lost
+/// acknowledgement to be noticed and sent again.
+const START_QUIET_SECS: f32 = 0.5;
+
+const MAX_PACKET_SIZE: usize = 2048;
+
+/// Announcements are sent to the broadcast addresses of both the LAN and the loopback network.
+const BROADCAST_ADDRS: [Ipv4Addr; 2] = [Ipv4Addr::BROADCAST, Ipv4Addr::new(127, 255, 255, 255)];
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LobbyRequest>()
+            .add_systems(OnEnter(GameState::Lobby), open_lobby)
+            .add_systems(OnExit(GameState::Lobby), close_lobby)
+            .add_systems(
+                Update,
+                (receive_messages, send_messages, handle_requests)
+                    .chain()
+                    .run_if(in_state(GameState::Lobby).and(resource_exists::<Lobby>)),
+            );
+    }
+}
+
+/// Whether to host a game or look for one in the lobby. This is set by the main menu.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyRole {
+    Host,
+    Client,
+}
+
+/// Event sent by the lobby screen.
+#[derive(Event, Debug)]
+pub enum LobbyRequest {
+    /// Join the game hosted at this address.
+    Join(SocketAddr),
+    /// Start the game for everyone who has joined. Only used by the host.
+    Start,
+}
+
+/// A game announced by a host.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Announcement {
+    /// A random ID for the game. A host on the same machine is heard over both the LAN and
+    /// loopback, so this is used to only list it once.
+    pub id: u64,
+    /// The level chosen by the host.
+    pub level: LevelId,
+    /// The number of players who have joined, including the host.
+    pub players: usize,
+    pub max_players: usize,
+    pub ruleset: Ruleset,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum LobbyMessage {
+    Announce(Announcement),
+    /// Sent by a client to join a game, with the port it will use for netplay.
+    Join {
+        game_port: u16,
+    },
+    /// Sent by the host to every client to start the game.
+    Start {
+        /// The netplay address of each player, where `None` is the host.
+        players: Vec<Option<SocketAddr>>,
+        /// The index of the client receiving this message in `players`.
+        you: usize,
+        /// The netplay port of the host.
+        host_game_port: u16,
+        seed: u64,
+        ruleset: Ruleset,
+        level: LevelId,
+    },
+    /// Sent by a client for each `Start` message it receives.
+    Started,
+}
+
+/// The settings of a game that is about to start.
+#[derive(Debug)]
+pub struct PendingGame {
+    players: Vec<NetplayPlayer>,
+    seed: u64,
+    ruleset: Ruleset,
+    level: LevelId,
+}
+
+/// A client which has joined a game, as seen by the host.
+#[derive(Debug, Clone)]
+pub struct LobbyClient {
+    /// The address the client sends lobby messages from.
+    pub lobby_addr: SocketAddr,
+    /// The address the client will use for netplay.
+    pub game_addr: SocketAddr,
+}
+
+/// A game found by a client.
+#[derive(Debug)]
+pub struct DiscoveredGame {
+    /// The address the host sends lobby messages from.
+    pub host: SocketAddr,
+    pub announcement: Announcement,
+    /// The time since the game was last announced.
+    since_announced: f32,
+}
+
+#[derive(Debug)]
+pub enum LobbyState {
+    Hosting {
+        clients: Vec<LobbyClient>,
+    },
+    /// The host has sent every client the players, and is waiting for them to acknowledge it.
+    Starting {
+        clients: Vec<LobbyClient>,
+        game: PendingGame,
+        /// The lobby addresses of the clients who haven't acknowledged the start yet.
+        unacknowledged: Vec<SocketAddr>,
+    },
+    Searching {
+        games: Vec<DiscoveredGame>,
+    },
+    Joined {
+        host: SocketAddr,
+    },
+    /// A client has been sent the players, and is waiting for the host to stop sending them.
+    Acknowledged {
+        host: SocketAddr,
+        game: PendingGame,
+        /// The time since the host last sent the players.
+        since_start: f32,
+    },
+}
+
+/// Resource for an open lobby, which is removed when leaving the lobby screen.
+#[derive(Resource, Debug)]
+pub struct Lobby {
+    /// Used to send messages, and to receive them other than announcements.
+    socket: UdpSocket,
+    /// Used by clients to listen for announcements.
+    discovery_socket: Option<UdpSocket>,
+    /// The socket to use for netplay once the game starts. This is bound when the lobby opens, so
+    /// that the port given to the other players can't be taken in the meantime.
+    game_socket: UdpSocket,
+    game_port: u16,
+    /// The ID announced by a host.
+    id: u64,
+    state: LobbyState,
+}
+
+impl Lobby {
+    fn new(role: LobbyRole, id: u64) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        let (discovery_socket, state) = match role {
+            LobbyRole::Host => (
+                None,
+                LobbyState::Hosting {
+                    clients: Vec::new(),
+                },
+            ),
+            LobbyRole::Client => (
+                Some(discovery_socket()?),
+                LobbyState::Searching { games: Vec::new() },
+            ),
+        };
+
+        let game_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let game_port = game_socket.local_addr()?.port();
+
+        Ok(Self {
+            socket,
+            discovery_socket,
+            game_socket,
+            game_port,
+            id,
+            state,
+        })
+    }
+
+    pub fn state(&self) -> &LobbyState {
+        &self.state
+    }
+
+    pub fn game_port(&self) -> u16 {
+        self.game_port
+    }
+
+    fn send(&self, message: &LobbyMessage, addr: SocketAddr) {
+        let bytes = serde_json::to_vec(message).expect("failed to serialise lobby message");
+        if let Err(e) = self.socket.send_to(&bytes, addr) {
+            // Broadcasts fail on networks without a broadcast route, so don't warn every second.
+            debug!("failed to send lobby message to {addr}: {e}");
+        }
+    }
+
+    /// Send every client who hasn't acknowledged the start of the game the players.
+    fn send_start(&self) {
+        let LobbyState::Starting {
+            clients,
+            game,
+            unacknowledged,
+        } = &self.state
+        else {
+            return;
+        };
+
+        let players = [None]
+            .into_iter()
+            .chain(clients.iter().map(|client| Some(client.game_addr)))
+            .collect::<Vec<_>>();
+        for (you, client) in clients.iter().enumerate().map(|(i, c)| (i + 1, c)) {
+            if !unacknowledged.contains(&client.lobby_addr) {
+                continue;
+            }
+            self.send(
+                &LobbyMessage::Start {
+                    players: players.clone(),
+                    you,
+                    host_game_port: self.game_port,
+                    seed: game.seed,
+                    ruleset: game.ruleset.clone(),
+                    level: game.level.clone(),
+                },
+                client.lobby_addr,
+            );
+        }
+    }
+}
+
+/// Bind a socket to the discovery port. Several clients on one machine can do this at once, and
+/// they all receive each announcement.
+fn discovery_socket() -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Read every waiting message from `socket`, ignoring anything which isn't a lobby message.
+fn receive(socket: &UdpSocket) -> Vec<(LobbyMessage, SocketAddr)> {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut messages = Vec::new();
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, src)) => match serde_json::from_slice(&buf[..len]) {
+                Ok(message) => messages.push((message, src)),
+                Err(e) => debug!("ignoring invalid lobby message from {src}: {e}"),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("failed to receive lobby message: {e}");
+                break;
+            }
+        }
+    }
+
+    messages
+}
+
+fn open_lobby(
+    mut commands: Commands,
+    role: Res<LobbyRole>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    // This must be unique to each lobby, even when the seed is set in the config.
+    match Lobby::new(*role, rand::random()) {
+        Ok(lobby) => {
+            info!("opened {role:?} lobby");
+            commands.insert_resource(lobby);
+        }
+        Err(e) => {
+            error!("failed to open lobby: {e}");
+            next_state.set(GameState::MainMenu);
+        }
+    }
+}
+
+fn close_lobby(mut commands: Commands) {
+    commands.remove_resource::<Lobby>();
+}
+
+fn receive_messages(
+    mut commands: Commands,
+    mut lobby: ResMut<Lobby>,
+    settings: Option<Res<NetplaySettings>>,
+    time: Res<Time>,
+) {
+    let mut messages = receive(&lobby.socket);
+    if let Some(discovery_socket) = &lobby.discovery_socket {
+        messages.extend(receive(discovery_socket));
+    }
+
+    // Clients acknowledge every `Start` message from the host, in case an acknowledgement was lost.
+    let mut acknowledge = None;
+    let mut acknowledged_state = None;
+    match &mut lobby.state {
+        LobbyState::Hosting { clients } => {
+            for (message, src) in messages {
+                let LobbyMessage::Join { game_port } = message else {
+                    continue;
+                };
+                if clients.iter().any(|client| client.lobby_addr == src) {
+                    continue;
+                }
+                if clients.len() + 1 >= MAX_PLAYERS {
+                    warn!("lobby is full, ignoring request to join from {src}");
+                    continue;
+                }
+
+                info!("{src} joined the lobby");
+                clients.push(LobbyClient {
+                    lobby_addr: src,
+                    game_addr: SocketAddr::new(src.ip(), game_port),
+                });
+            }
+        }
+        LobbyState::Starting { unacknowledged, .. } => {
+            for (message, src) in messages {
+                if let LobbyMessage::Started = message {
+                    unacknowledged.retain(|addr| *addr != src);
+                }
+            }
+        }
+        LobbyState::Searching { games } => {
+            for game in games.iter_mut() {
+                game.since_announced += time.delta_secs();
+            }
+            games.retain(|game| game.since_announced < GAME_TIMEOUT_SECS);
+
+            for (message, src) in messages {
+                let LobbyMessage::Announce(announcement) = message else {
+                    continue;
+                };
+                match games
+                    .iter_mut()
+                    .find(|game| game.announcement.id == announcement.id)
+                {
+                    Some(game) => {
+                        game.announcement = announcement;
+                        game.since_announced = 0.0;
+                    }
+                    None => games.push(DiscoveredGame {
+                        host: src,
+                        announcement,
+                        since_announced: 0.0,
+                    }),
+                }
+            }
+        }
+        LobbyState::Joined { host } => {
+            for (message, src) in messages {
+                let LobbyMessage::Start {
+                    players,
+                    you,
+                    host_game_port,
+                    seed,
+                    ruleset,
+                    level,
+                } = message
+                else {
+                    continue;
+                };
+                if src != *host {
+                    continue;
+                }
+
+                let players = players
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, addr)| match addr {
+                        _ if i == you => NetplayPlayer::Local,
+                        Some(addr) => NetplayPlayer::Remote(addr),
+                        None => NetplayPlayer::Remote(SocketAddr::new(host.ip(), host_game_port)),
+                    })
+                    .collect();
+
+                info!("the host has started the game");
+                acknowledge = Some(*host);
+                acknowledged_state = Some(LobbyState::Acknowledged {
+                    host: *host,
+                    game: PendingGame {
+                        players,
+                        seed,
+                        ruleset,
+                        level,
+                    },
+                    since_start: 0.0,
+                });
+                break;
+            }
+        }
+        LobbyState::Acknowledged {
+            host, since_start, ..
+        } => {
+            *since_start += time.delta_secs();
+            if messages
+                .iter()
+                .any(|(message, src)| matches!(message, LobbyMessage::Start { .. }) && src == host)
+            {
+                acknowledge = Some(*host);
+                *since_start = 0.0;
+            }
+        }
+    }
+
+    if let Some(host) = acknowledge {
+        lobby.send(&LobbyMessage::Started, host);
+    }
+    if let Some(state) = acknowledged_state {
+        lobby.state = state;
+    }
+
+    // Start once everyone knows who is playing.
+    let ready = match &lobby.state {
+        LobbyState::Starting { unacknowledged, .. } => unacknowledged.is_empty(),
+        LobbyState::Acknowledged { since_start, .. } => *since_start >= START_QUIET_SECS,
+        _ => false,
+    };
+    if ready && settings.is_none() {
+        start_game(&mut commands, &lobby);
+    }
+}
+
+/// Announce the game as the host, or repeat the request to join a game as a client.
+fn send_messages(
+    lobby: Res<Lobby>,
+    ruleset: Res<Ruleset>,
+    level_selection: Res<LevelSelection>,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+) {
+    // The players are sent every frame until every client has acknowledged them.
+    if let LobbyState::Starting { .. } = lobby.state {
+        lobby.send_start();
+        return;
+    }
+
+    let timer = timer
+        .get_or_insert_with(|| Timer::from_seconds(ANNOUNCE_INTERVAL_SECS, TimerMode::Repeating));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    match &lobby.state {
+        LobbyState::Hosting { clients } => {
+            let announcement = LobbyMessage::Announce(Announcement {
+                id: lobby.id,
+                level: LevelId::from_selection(&level_selection),
+                players: clients.len() + 1,
+                max_players: MAX_PLAYERS,
+                ruleset: ruleset.clone(),
+            });
+            for addr in BROADCAST_ADDRS {
+                lobby.send(&announcement, SocketAddr::from((addr, DISCOVERY_PORT)));
+            }
+        }
+        LobbyState::Joined { host } => {
+            lobby.send(
+                &LobbyMessage::Join {
+                    game_port: lobby.game_port,
+                },
+                *host,
+            );
+        }
+        LobbyState::Starting { .. }
+        | LobbyState::Searching { .. }
+        | LobbyState::Acknowledged { .. } => {}
+    }
+}
+
+fn handle_requests(
+    mut lobby: ResMut<Lobby>,
+    mut ev_request: EventReader<LobbyRequest>,
+    ruleset: Res<Ruleset>,
+    level_selection: Res<LevelSelection>,
+    mut rng: ResMut<GameRng>,
+) {
+    for request in ev_request.read() {
+        match (request, &lobby.state) {
+            (LobbyRequest::Join(host), LobbyState::Searching { .. }) => {
+                info!("joining game hosted by {host}");
+                lobby.send(
+                    &LobbyMessage::Join {
+                        game_port: lobby.game_port,
+                    },
+                    *host,
+                );
+                lobby.state = LobbyState::Joined { host: *host };
+            }
+            (LobbyRequest::Start, LobbyState::Hosting { clients }) => {
+                if clients.is_empty() {
+                    warn!("can't start a game without any other players");
+                    continue;
+                }
+
+                let players = [NetplayPlayer::Local]
+                    .into_iter()
+                    .chain(
+                        clients
+                            .iter()
+                            .map(|client| NetplayPlayer::Remote(client.game_addr)),
+                    )
+                    .collect();
+                info!("starting LAN game, waiting for every player to hear about it");
+                lobby.state = LobbyState::Starting {
+                    clients: clients.clone(),
+                    game: PendingGame {
+                        players,
+                        seed: rng.0.gen(),
+                        ruleset: ruleset.clone(),
+                        level: LevelId::from_selection(&level_selection),
+                    },
+                    unacknowledged: clients.iter().map(|client| client.lobby_addr).collect(),
+                };
+                lobby.send_start();
+                return;
+            }
+            (request, state) => warn!("ignoring lobby request {request:?} in state {state:?}"),
+        }
+    }
+}
+
+/// Start a netplay session with the settings agreed in the lobby, on the lobby's game socket.
+fn start_game(commands: &mut Commands, lobby: &Lobby) {
+    let (LobbyState::Starting { game, .. } | LobbyState::Acknowledged { game, .. }) = &lobby.state
+    else {
+        return;
+    };
+    let socket = match lobby.game_socket.try_clone() {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("failed to start LAN game: {e}");
+            return;
+        }
+    };
+    info!("starting LAN game with players {:?}", game.players);
+
+    // Every peer must play the same level, whatever each of them had selected before.
+    commands.insert_resource(game.ruleset.clone());
+    commands.insert_resource(game.level.to_selection());
+    commands.insert_resource(NetplaySettings {
+        mode: NetplayMode::P2P {
+            socket,
+            players: game.players.clone(),
+        },
+        seed: game.seed,
+    });
+}
@@ -1,72 +1,129 @@
 #![warn(clippy::semicolon_if_nothing_returned, clippy::uninlined_format_args)]
 
 use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
 
-use rand::{rngs::SmallRng, SeedableRng};
+use std::net::{Ipv4Addr, UdpSocket};
 
-mod audio;
-mod bomb;
-mod camera;
-mod config;
-mod debug;
-mod ldtk;
-mod player;
-mod ui;
-mod z_sort;
-
-#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum GameState {
-    /// For some reason, the first `StateTransition` schedule seems to happen before `PreStartup`.
-    /// So, in order to initialise resources to be used in `MainMenu`, we have to initialise the
-    /// state with the dummy variant `PreLoad`, which is immediately transitioned to `MainMenu` in
-    /// the first `Startup` schedule. This seems to me like a scheduling bug in bevy, but I haven't
-    /// opened an issue yet.
-    #[default]
-    PreLoad,
-    MainMenu,
-    LoadingLevel,
-    InGame,
-}
-
-fn go_to_menu(mut next_state: ResMut<NextState<GameState>>) {
-    next_state.set(GameState::MainMenu);
-}
-
-#[derive(Resource)]
-pub struct GameRng(SmallRng);
+use bomby::{
+    arena, armour, ascii_map, audio, bomb, bot, camera, cli, client, config, debris, debug, door,
+    go_to_menu, ldtk, lobby, netplay, player, playlist, replay, round, rules, sim, snapshot,
+    start_level, sync_test, teleporter, tide, ui, z_sort, CosmeticRng, GameRng, GameState,
+};
 
 fn main() {
+    let args = cli::Args::parse();
     let config = config::load_config();
     info!("Initialised config: {config:?}");
 
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        resolution: (config.window_width, config.window_height).into(),
-                        title: "Bomby!".to_string(),
-                        resizable: config.window_resizable,
-                        ..default()
-                    }),
+    let configured_seed = args.seed.or(config.seed);
+    if args.simulate.is_some() {
+        sim::run(config, &args, configured_seed.unwrap_or_default());
+        return;
+    }
+
+    let seed = configured_seed.unwrap_or_else(rand::random);
+
+    let mut app = App::new();
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    resolution: (config.window_width, config.window_height).into(),
+                    title: "Bomby!".to_string(),
+                    resizable: config.window_resizable,
                     ..default()
                 }),
-        )
-        .init_state::<GameState>()
-        .insert_resource(config)
-        .add_plugins((
-            bevy_kira_audio::AudioPlugin,
-            audio::AudioPlugin,
-            debug::DebugPlugin,
-            player::PlayerPlugin,
-            ldtk::BombyLdtkPlugin,
-            bomb::BombPlugin,
-            camera::CameraPlugin,
-            ui::UiPlugin,
-            z_sort::ZSortPlugin,
-        ))
-        .insert_resource(GameRng(SmallRng::from_entropy()))
-        .add_systems(Startup, go_to_menu)
-        .run();
+                ..default()
+            }),
+    )
+    .init_state::<GameState>()
+    .insert_resource(config.ruleset.clone())
+    .insert_resource(config)
+    .add_plugins((
+        bevy_kira_audio::AudioPlugin,
+        audio::AudioPlugin,
+        debug::DebugPlugin,
+        sync_test::SyncTestPlugin,
+        replay::ReplayPlugin,
+        snapshot::SnapshotPlugin,
+        camera::CameraPlugin,
+        debris::DebrisPlugin,
+        ui::UiPlugin,
+        lobby::LobbyPlugin,
+        client::ClientPlugin,
+        z_sort::ZSortPlugin,
+    ))
+    // Gameplay plugins. Keep these in sync with `sim::headless_app`.
+    .add_plugins((
+        player::PlayerPlugin,
+        ldtk::BombyLdtkPlugin,
+        arena::ArenaPlugin,
+        ascii_map::AsciiMapPlugin,
+        bomb::BombPlugin,
+        teleporter::TeleporterPlugin,
+        door::DoorPlugin,
+        tide::TidePlugin,
+        armour::ArmourPlugin,
+        bot::BotPlugin,
+        round::RoundPlugin,
+        playlist::PlaylistPlugin,
+        rules::RulesPlugin,
+        netplay::NetplayPlugin,
+    ))
+    .insert_resource(GameRng::from_seed(seed))
+    .insert_resource(CosmeticRng::from_seed(seed));
+    info!("using seed {seed}");
+
+    let netplay_mode = match (args.sync_test, args.netplay) {
+        (Some(check_distance), _) => Some(netplay::NetplayMode::SyncTest { check_distance }),
+        (None, Some(port)) => match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+            Ok(socket) => Some(netplay::NetplayMode::P2P {
+                socket,
+                players: args.players,
+            }),
+            Err(e) => {
+                error!("failed to bind to netplay port {port}: {e}");
+                return;
+            }
+        },
+        (None, None) => None,
+    };
+
+    if let Some(map) = &args.map {
+        app.insert_resource(LevelSelection::Identifier(map.clone()));
+    }
+
+    if let Some(path) = args.record {
+        app.insert_resource(replay::ReplayRecorder::new(path, seed));
+    }
+
+    if let Some(mode) = netplay_mode {
+        app.insert_resource(netplay::NetplaySettings {
+            mode,
+            seed: configured_seed.unwrap_or_default(),
+        });
+    } else if let Some(path) = args.replay {
+        match replay::ReplayPlayer::load(&path) {
+            Ok(player) => {
+                app.insert_resource(player);
+            }
+            Err(e) => {
+                error!("failed to load replay {path:?}: {e}");
+                return;
+            }
+        }
+    } else if let Some(server) = args.connect {
+        app.insert_resource(client::ClientSettings { server });
+    } else if let Some(path) = args.snapshot {
+        app.insert_resource(snapshot::SnapshotPath(path))
+            .add_systems(Startup, snapshot::load_snapshot);
+    } else if args.map.is_some() {
+        app.add_systems(Startup, start_level);
+    } else {
+        app.add_systems(Startup, go_to_menu);
+    }
+
+    app.run();
 }
@@ -0,0 +1,768 @@
+//! Online versus mode using rollback netplay over UDP, started with `--netplay`. Each peer only
+//! sends its players' inputs. When a remote input turns out to be different to what was predicted,
+//! the gameplay state is rolled back to the last confirmed frame and re-simulated.
+//!
+//! For this to work, everything in the [`Gameplay`] schedule must be deterministic: given the same
+//! state and inputs, every peer must end up with the same state. All state read or written by
+//! those systems must also be registered for rollback in [`NetplayPlugin`].
+//!
+//! To test with two instances on one machine:
+//!
+//! ```console
+//! $ cargo run -- --netplay 7000 --players local,127.0.0.1:7001
+//! $ cargo run -- --netplay 7001 --players 127.0.0.1:7000,local
+//! ```
+//!
+//! Each round is played in its own session, which starts once every peer has loaded the level and
+//! spawned the players, so that every peer starts the round from the same state on the same frame.
+//! The next round is only loaded once the end of the round has been confirmed, as a rollback could
+//! otherwise carry on a round that one peer has already left. For the same reason, the sound
+//! effects, camera trauma and explosions sent by the gameplay are held back until their frame has
+//! been confirmed, so that they aren't played again each time the frame is re-simulated.
+//!
+//! To check that the gameplay is deterministic, use the sync test mode in [`sync_test`](crate::sync_test).
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::{TileTextureIndex, TileVisible};
+use bevy_ggrs::{
+    ggrs::{DesyncDetection, GgrsEvent, Message, NonBlockingSocket, P2PSession, SessionState},
+    prelude::*,
+    RollbackFrameCount,
+};
+use leafwing_input_manager::prelude::*;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    io,
+    net::{AddrParseError, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    app::AppExit,
+    ecs::schedule::{ExecutorKind, ScheduleLabel},
+    utils::Instant,
+};
+
+use crate::{
+    armour::Armour,
+    audio::PlaySfx,
+    bomb::{Bomb, CountBombs, Explosion},
+    camera::CameraTrauma,
+    client::ServerConnection,
+    config::Config,
+    door::{Door, Switch},
+    ldtk::{Blocker, Destroyed},
+    player::{
+        input_map, CollisionBounds, CountPlayers, Eliminated, Knockback, Player, PlayerAction,
+        PlayerAnimator, PlayerController, Velocity,
+    },
+    replay::ReplayPlayer,
+    round::Round,
+    teleporter::Teleportable,
+    z_sort::ZSort,
+    GameRng, GameState,
+};
+
+pub struct NetplayPlugin;
+
+pub type NetplayConfig = GgrsConfig<u8>;
+
+/// The number of frames simulated per second, both online and offline.
+const FPS: usize = 60;
+
+/// The number of frames to delay local inputs by. This hides some latency, so that fewer
+/// rollbacks are needed.
+const INPUT_DELAY_FRAMES: usize = 2;
+
+/// How often, in frames, peers compare checksums of their state to detect a desync.
+const DESYNC_CHECK_INTERVAL: u32 = 10;
+
+/// The maximum number of players in a session, as there are only four spawn points.
+pub const MAX_PLAYERS: usize = 4;
+
+const INPUT_UP: u8 = 1 << 0;
+const INPUT_DOWN: u8 = 1 << 1;
+const INPUT_LEFT: u8 = 1 << 2;
+const INPUT_RIGHT: u8 = 1 << 3;
+const INPUT_BOMB: u8 = 1 << 4;
+/// Every bit used by an input.
+pub const INPUT_MASK: u8 = INPUT_UP | INPUT_DOWN | INPUT_LEFT | INPUT_RIGHT | INPUT_BOMB;
+
+/// How far an axis needs to be pushed to count as a direction.
+const AXIS_THRESHOLD: f32 = 0.5;
+
+/// The size of the buffer for receiving a message, which is larger than any message GGRS sends.
+const RECV_BUFFER_SIZE: usize = 4096;
+
+impl Plugin for NetplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(GgrsPlugin::<NetplayConfig>::default())
+            .set_rollback_schedule_fps(FPS)
+            // Systems which aren't explicitly ordered could otherwise run in a different order on
+            // each peer.
+            .edit_schedule(Gameplay, |schedule| {
+                schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+            })
+            .rollback_resource_with_clone::<GameRng>()
+            .rollback_resource_with_clone::<Round>()
+            .rollback_resource_with_clone::<RoundFinish>()
+            .rollback_component_with_clone::<Player>()
+            .rollback_component_with_clone::<Transform>()
+            .rollback_component_with_clone::<Visibility>()
+            .rollback_component_with_clone::<Sprite>()
+            .rollback_component_with_clone::<Name>()
+            .rollback_component_with_clone::<ZSort>()
+            .rollback_component_with_clone::<ActionState<PlayerAction>>()
+            .rollback_component_with_clone::<Velocity>()
+            .rollback_component_with_clone::<Knockback>()
+            .rollback_component_with_clone::<PlayerAnimator>()
+            .rollback_component_with_clone::<CollisionBounds>()
+            .rollback_component_with_clone::<CountBombs>()
+            .rollback_component_with_clone::<Teleportable>()
+            .rollback_component_with_clone::<Eliminated>()
+            .rollback_component_with_clone::<Bomb>()
+            .rollback_component_with_clone::<Destroyed>()
+            .rollback_component_with_clone::<Armour>()
+            .rollback_component_with_clone::<TileTextureIndex>()
+            .rollback_component_with_clone::<TileVisible>()
+            .rollback_component_with_clone::<Switch>()
+            .rollback_component_with_clone::<Door>()
+            .rollback_component_with_clone::<Blocker>()
+            .checksum_component_with_hash::<Player>()
+            .checksum_component::<Transform>(checksum_transform)
+            .add_systems(
+                Update,
+                start_netplay.run_if(resource_added::<NetplaySettings>),
+            )
+            .add_systems(
+                OnEnter(GameState::InGame),
+                start_session.run_if(resource_exists::<NetplaySettings>),
+            )
+            .add_systems(
+                OnExit(GameState::InGame),
+                end_session.run_if(resource_exists::<Session<NetplayConfig>>),
+            )
+            .add_systems(ReadInputs, read_local_inputs.in_set(NetplaySet::ReadInputs))
+            .add_systems(
+                GgrsSchedule,
+                (
+                    clear_effects,
+                    apply_inputs,
+                    run_gameplay,
+                    (record_effects, record_round_finish),
+                )
+                    .chain()
+                    .in_set(NetplaySet::Simulate),
+            )
+            .add_systems(
+                Update,
+                (
+                    run_gameplay
+                        .in_set(NetplaySet::Local)
+                        .run_if(gameplay_is_local),
+                    add_level_rollback.run_if(resource_exists::<NetplaySettings>),
+                    (
+                        log_session_events,
+                        (release_effects, end_confirmed_round).in_set(NetplaySet::Effects),
+                    )
+                        .run_if(resource_exists::<Session<NetplayConfig>>),
+                    poll_ending_session.run_if(resource_exists::<EndingSession>),
+                ),
+            );
+    }
+}
+
+/// Schedule for the systems that change the gameplay state, which must be deterministic. For local
+/// play this runs once per frame in `Update`. During netplay it runs in the rollback schedule at a
+/// fixed rate, and may run several times in a frame to re-simulate after a misprediction.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Gameplay;
+
+/// The netplay systems which other modules may need to be ordered against.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetplaySet {
+    /// Reading the local inputs in `ReadInputs`.
+    ReadInputs,
+    /// Running the [`Gameplay`] schedule for a frame in `GgrsSchedule`.
+    Simulate,
+    /// Running the [`Gameplay`] schedule once per frame in `Update`, during local play.
+    Local,
+    /// Sending the [`PlaySfx`], [`CameraTrauma`] and [`Explosion`] events of confirmed frames in
+    /// `Update`, during netplay. Systems reading these events in `Update` should run after this.
+    Effects,
+}
+
+fn run_gameplay(world: &mut World) {
+    world.run_schedule(Gameplay);
+}
+
+/// Run condition for running the [`Gameplay`] schedule once per frame in `Update`. The gameplay is
+/// driven from elsewhere during netplay, replay playback, or when connected to a server.
+pub fn gameplay_is_local(
+    settings: Option<Res<NetplaySettings>>,
+    replay: Option<Res<ReplayPlayer>>,
+    connection: Option<Res<ServerConnection>>,
+) -> bool {
+    settings.is_none() && replay.is_none() && connection.is_none()
+}
+
+/// A player in a netplay session, as given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetplayPlayer {
+    /// A player on this machine, written as `local`.
+    Local,
+    Remote(SocketAddr),
+}
+
+impl FromStr for NetplayPlayer {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            _ => s.parse().map(Self::Remote),
+        }
+    }
+}
+
+/// Resource used to start a netplay session, either from the command line or the LAN lobby.
+#[derive(Resource, Debug)]
+pub struct NetplaySettings {
+    pub mode: NetplayMode,
+    /// The seed for the [`GameRng`], which must be the same on every peer.
+    pub seed: u64,
+}
+
+#[derive(Debug)]
+pub enum NetplayMode {
+    /// Play online against other peers.
+    P2P {
+        /// The local socket to play on, which is bound before the session starts so that nothing
+        /// else can take its port after it has been given to the other peers.
+        socket: UdpSocket,
+        /// Every player in the session, in the same order on every peer.
+        players: Vec<NetplayPlayer>,
+    },
+    /// Play locally, rolling back `check_distance` frames on every frame to check that the
+    /// re-simulated state is the same.
+    SyncTest { check_distance: usize },
+}
+
+/// Run condition for systems that only run in the sync test mode.
+pub fn in_sync_test(session: Option<Res<Session<NetplayConfig>>>) -> bool {
+    matches!(session.as_deref(), Some(Session::SyncTest(_)))
+}
+
+/// Marker component for the entity which reads the local player's input during netplay. This is
+/// separate from the player entities, whose `ActionState` is set from the session's inputs.
+#[derive(Component)]
+struct LocalInput;
+
+/// Resource for the UDP socket that the session of every round plays on. Each message is sent
+/// with the number of the round it is for, so that messages still arriving from the last round's
+/// session aren't mistaken for messages from the next one.
+#[derive(Resource, Clone)]
+struct NetplaySocket {
+    shared: Arc<Mutex<SharedSocket>>,
+    /// The round that this handle sends and receives messages for.
+    round: u8,
+}
+
+struct SharedSocket {
+    socket: UdpSocket,
+    /// Messages that have been received for each round, but not read yet.
+    inboxes: HashMap<u8, Vec<(SocketAddr, Message)>>,
+}
+
+impl NetplaySocket {
+    fn new(socket: &UdpSocket) -> io::Result<Self> {
+        let socket = socket.try_clone()?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            shared: Arc::new(Mutex::new(SharedSocket {
+                socket,
+                inboxes: HashMap::new(),
+            })),
+            round: 0,
+        })
+    }
+
+    /// Get a handle to the socket for the session of the next round.
+    fn next_round(&mut self) -> Self {
+        self.round = self.round.wrapping_add(1);
+        self.clone()
+    }
+}
+
+impl NonBlockingSocket<SocketAddr> for NetplaySocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        let mut bytes = vec![self.round];
+        if let Err(e) = bincode::serialize_into(&mut bytes, msg) {
+            warn!("failed to serialise netplay message: {e}");
+            return;
+        }
+
+        // GGRS resends anything that doesn't arrive, so a failed send isn't a problem.
+        let shared = self.shared.lock().expect("netplay socket lock poisoned");
+        if let Err(e) = shared.socket.send_to(&bytes, addr) {
+            debug!("failed to send netplay message to {addr}: {e}");
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        let mut shared = self.shared.lock().expect("netplay socket lock poisoned");
+        let SharedSocket { socket, inboxes } = &mut *shared;
+
+        let mut buffer = [0; RECV_BUFFER_SIZE];
+        loop {
+            match socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => {
+                    let Some((round, bytes)) = buffer[..len].split_first() else {
+                        continue;
+                    };
+                    if let Ok(message) = bincode::deserialize(bytes) {
+                        inboxes.entry(*round).or_default().push((addr, message));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // Windows reports this when a message couldn't be delivered to a peer.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    warn!("failed to receive netplay messages: {e}");
+                    break;
+                }
+            }
+        }
+
+        // Only this round and the one before it can still have a session reading messages.
+        let round = self.round;
+        inboxes.retain(|inbox_round, _| inbox_round.wrapping_sub(round) as i8 >= -1);
+        inboxes.remove(&round).unwrap_or_default()
+    }
+}
+
+/// Resource for the session of the last round, which keeps talking to the other peers until they
+/// have all started the next round. Until then, they may still need our inputs to confirm the end
+/// of the last round.
+#[derive(Resource)]
+struct EndingSession(P2PSession<NetplayConfig>);
+
+/// Resource for the frame that the round finished on, if it has. This is rolled back, so a round
+/// which only finished in a mispredicted frame carries on as normal.
+#[derive(Resource, Default, Debug, Clone, Copy)]
+struct RoundFinish(Option<i32>);
+
+/// Resource for the cosmetic events sent by the gameplay in frames that haven't been confirmed yet,
+/// by frame. A frame's events are replaced each time it is re-simulated, and only sent on once it
+/// has been confirmed.
+#[derive(Resource, Default)]
+struct PendingEffects {
+    frames: BTreeMap<i32, FrameEffects>,
+    /// The last frame whose events have been sent on.
+    released: Option<i32>,
+}
+
+#[derive(Default)]
+struct FrameEffects {
+    sfx: Vec<PlaySfx>,
+    trauma: Vec<CameraTrauma>,
+    explosions: Vec<Explosion>,
+}
+
+/// Prepare to play the first round online. The session itself is only started once the level has
+/// loaded, in [`start_session`].
+fn start_netplay(
+    mut commands: Commands,
+    settings: Res<NetplaySettings>,
+    mut config: ResMut<Config>,
+    mut count_players: ResMut<CountPlayers>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let num_players = match session_players(&settings.mode) {
+        Ok(players) => players.len(),
+        Err(e) => {
+            error!("failed to start netplay: {e}");
+            exit.send(AppExit::error());
+            return;
+        }
+    };
+
+    if let NetplayMode::P2P { socket, .. } = &settings.mode {
+        match NetplaySocket::new(socket) {
+            Ok(socket) => commands.insert_resource(socket),
+            Err(e) => {
+                error!("failed to start netplay: {e}");
+                exit.send(AppExit::error());
+                return;
+            }
+        }
+    }
+    info!("starting netplay: {:?}", settings.mode);
+
+    commands.insert_resource(GameRng::from_seed(settings.seed));
+    commands.spawn((
+        InputManagerBundle::<PlayerAction> {
+            input_map: input_map(0),
+            ..default()
+        },
+        LocalInput,
+        Name::new("Local input"),
+    ));
+
+    config.players = vec![PlayerController::Network; num_players];
+    count_players.0 = num_players;
+
+    next_state.set(GameState::LoadingLevel);
+}
+
+/// Start the session for a round, once the level has loaded and the players have spawned.
+fn start_session(
+    mut commands: Commands,
+    settings: Res<NetplaySettings>,
+    socket: Option<ResMut<NetplaySocket>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let socket = socket.map(|mut socket| socket.next_round());
+    match build_session(&settings.mode, socket) {
+        Ok(session) => {
+            commands.insert_resource(session);
+            commands.insert_resource(RoundFinish::default());
+            commands.insert_resource(PendingEffects::default());
+        }
+        Err(e) => {
+            error!("failed to start netplay session: {e}");
+            exit.send(AppExit::error());
+        }
+    }
+}
+
+/// End the session of a round. A P2P session is kept as the [`EndingSession`] until the next round
+/// has started.
+fn end_session(world: &mut World) {
+    if let Some(Session::P2P(session)) = world.remove_resource::<Session<NetplayConfig>>() {
+        world.insert_resource(EndingSession(session));
+    }
+}
+
+fn poll_ending_session(
+    mut commands: Commands,
+    mut ending: ResMut<EndingSession>,
+    session: Option<Res<Session<NetplayConfig>>>,
+) {
+    ending.0.poll_remote_clients();
+
+    // Every peer has to have confirmed the end of the last round to start the next one.
+    if let Some(Session::P2P(session)) = session.as_deref() {
+        if session.current_state() == SessionState::Running {
+            commands.remove_resource::<EndingSession>();
+        }
+    }
+}
+
+/// The players in a session for `mode`, checking that there is a valid number of them.
+fn session_players(mode: &NetplayMode) -> Result<Vec<NetplayPlayer>, String> {
+    let players = match mode {
+        NetplayMode::P2P { players, .. } => players.clone(),
+        NetplayMode::SyncTest { .. } => vec![NetplayPlayer::Local; MAX_PLAYERS],
+    };
+
+    let count_players = players.len();
+    if !(2..=MAX_PLAYERS).contains(&count_players) {
+        return Err(format!(
+            "expected 2 to {MAX_PLAYERS} players, found {count_players}"
+        ));
+    }
+    Ok(players)
+}
+
+/// Build the session for `mode`. P2P sessions play on `socket`.
+fn build_session(
+    mode: &NetplayMode,
+    socket: Option<NetplaySocket>,
+) -> Result<Session<NetplayConfig>, String> {
+    let players = session_players(mode)?;
+
+    let mut builder = SessionBuilder::<NetplayConfig>::new()
+        .with_num_players(players.len())
+        .with_input_delay(INPUT_DELAY_FRAMES)
+        .with_desync_detection_mode(DesyncDetection::On {
+            interval: DESYNC_CHECK_INTERVAL,
+        });
+
+    for (handle, player) in players.iter().enumerate() {
+        let player_type = match player {
+            NetplayPlayer::Local => PlayerType::Local,
+            NetplayPlayer::Remote(addr) => PlayerType::Remote(*addr),
+        };
+        builder = builder
+            .add_player(player_type, handle)
+            .map_err(|e| format!("invalid player {player:?}: {e}"))?;
+    }
+
+    let session = match mode {
+        NetplayMode::P2P { .. } => {
+            let socket = socket.ok_or("there is no socket to play on")?;
+            Session::P2P(
+                builder
+                    .start_p2p_session(socket)
+                    .map_err(|e| e.to_string())?,
+            )
+        }
+        NetplayMode::SyncTest { check_distance } => Session::SyncTest(
+            builder
+                .with_check_distance(*check_distance)
+                .start_synctest_session()
+                .map_err(|e| e.to_string())?,
+        ),
+    };
+
+    Ok(session)
+}
+
+/// The last frame whose inputs have been confirmed, so won't be re-simulated.
+fn confirmed_frame(
+    session: &Session<NetplayConfig>,
+    settings: &NetplaySettings,
+    frame: &RollbackFrameCount,
+) -> i32 {
+    match (session, &settings.mode) {
+        (Session::P2P(session), _) => session.confirmed_frame(),
+        // A sync test re-simulates each frame until it is `check_distance` frames old.
+        (_, NetplayMode::SyncTest { check_distance }) => frame.0 - *check_distance as i32,
+        _ => frame.0,
+    }
+}
+
+/// Drop the events sent on in the last `Update`, which have already been read, so that the
+/// gameplay doesn't read them again and they aren't recorded for this frame.
+fn clear_effects(
+    mut ev_sfx: ResMut<Events<PlaySfx>>,
+    mut ev_trauma: ResMut<Events<CameraTrauma>>,
+    mut ev_explosion: ResMut<Events<Explosion>>,
+) {
+    ev_sfx.clear();
+    ev_trauma.clear();
+    ev_explosion.clear();
+}
+
+/// Take the cosmetic events sent by the gameplay in this frame, to be sent on once it is confirmed.
+fn record_effects(
+    frame: Res<RollbackFrameCount>,
+    mut pending: ResMut<PendingEffects>,
+    mut ev_sfx: ResMut<Events<PlaySfx>>,
+    mut ev_trauma: ResMut<Events<CameraTrauma>>,
+    mut ev_explosion: ResMut<Events<Explosion>>,
+) {
+    let effects = FrameEffects {
+        sfx: ev_sfx.drain().collect(),
+        trauma: ev_trauma.drain().collect(),
+        explosions: ev_explosion.drain().collect(),
+    };
+
+    // A frame can't change once it is confirmed, so its events have already been sent on.
+    if pending.released.is_some_and(|released| frame.0 <= released) {
+        return;
+    }
+    pending.frames.insert(frame.0, effects);
+}
+
+fn record_round_finish(
+    round: Res<Round>,
+    frame: Res<RollbackFrameCount>,
+    mut finish: ResMut<RoundFinish>,
+) {
+    if round.is_finished() && finish.0.is_none() {
+        finish.0 = Some(frame.0);
+    }
+}
+
+/// Send on the cosmetic events of the frames which have been confirmed since the last `Update`.
+fn release_effects(
+    mut pending: ResMut<PendingEffects>,
+    session: Res<Session<NetplayConfig>>,
+    settings: Res<NetplaySettings>,
+    frame: Res<RollbackFrameCount>,
+    mut ev_sfx: EventWriter<PlaySfx>,
+    mut ev_trauma: EventWriter<CameraTrauma>,
+    mut ev_explosion: EventWriter<Explosion>,
+) {
+    let confirmed = confirmed_frame(&session, &settings, &frame);
+    if pending
+        .released
+        .is_some_and(|released| confirmed <= released)
+    {
+        return;
+    }
+
+    let unconfirmed = pending.frames.split_off(&(confirmed + 1));
+    for effects in std::mem::replace(&mut pending.frames, unconfirmed).into_values() {
+        ev_sfx.send_batch(effects.sfx);
+        ev_trauma.send_batch(effects.trauma);
+        ev_explosion.send_batch(effects.explosions);
+    }
+    pending.released = Some(confirmed);
+}
+
+/// Load the next round once the end of this one has been confirmed, so that it can't be undone.
+fn end_confirmed_round(
+    finish: Res<RoundFinish>,
+    session: Res<Session<NetplayConfig>>,
+    settings: Res<NetplaySettings>,
+    frame: Res<RollbackFrameCount>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if finish
+        .0
+        .is_some_and(|finished| finished <= confirmed_frame(&session, &settings, &frame))
+    {
+        next_state.set(GameState::LoadingLevel);
+    }
+}
+
+fn read_local_inputs(
+    mut commands: Commands,
+    local_players: Res<LocalPlayers>,
+    local_input: Query<&ActionState<PlayerAction>, With<LocalInput>>,
+) {
+    let input = local_input.get_single().map_or(0, encode_input);
+    let local_inputs = local_players
+        .0
+        .iter()
+        .map(|handle| (*handle, input))
+        .collect();
+
+    commands.insert_resource(LocalInputs::<NetplayConfig>(local_inputs));
+}
+
+/// Encode the input in an `ActionState` as bits, to be sent over the network.
+pub fn encode_input(action_state: &ActionState<PlayerAction>) -> u8 {
+    let movement = action_state.axis_pair(&PlayerAction::Move);
+    let mut input = 0;
+
+    if movement.y > AXIS_THRESHOLD {
+        input |= INPUT_UP;
+    }
+    if movement.y < -AXIS_THRESHOLD {
+        input |= INPUT_DOWN;
+    }
+    if movement.x < -AXIS_THRESHOLD {
+        input |= INPUT_LEFT;
+    }
+    if movement.x > AXIS_THRESHOLD {
+        input |= INPUT_RIGHT;
+    }
+    if action_state.pressed(&PlayerAction::Bomb) {
+        input |= INPUT_BOMB;
+    }
+
+    input
+}
+
+fn decode_movement(input: u8) -> Vec2 {
+    let axis = |negative, positive| match (input & negative != 0, input & positive != 0) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => 0.0,
+    };
+
+    Vec2::new(axis(INPUT_LEFT, INPUT_RIGHT), axis(INPUT_DOWN, INPUT_UP))
+}
+
+/// Set the `ActionState` of each player from the session's inputs for this frame.
+fn apply_inputs(
+    mut players: Query<(&Player, &mut ActionState<PlayerAction>)>,
+    inputs: Res<PlayerInputs<NetplayConfig>>,
+) {
+    for (player, mut action_state) in players.iter_mut() {
+        let Some((input, _)) = inputs.get(player.0) else {
+            continue;
+        };
+
+        apply_input(&mut action_state, *input);
+    }
+}
+
+/// Set the `ActionState` of a player from the input bits made by [`encode_input`].
+pub fn apply_input(action_state: &mut ActionState<PlayerAction>, input: u8) {
+    set_action_state(
+        action_state,
+        input & INPUT_BOMB != 0,
+        decode_movement(input),
+    );
+}
+
+/// Set the `ActionState` of a player who isn't controlled by an `InputMap`. leafwing only ticks the
+/// `ActionState` once per frame, so it is ticked here too in order for `just_pressed` to work when
+/// several frames are simulated at once.
+pub fn set_action_state(action_state: &mut ActionState<PlayerAction>, bomb: bool, movement: Vec2) {
+    let now = Instant::now();
+    action_state.tick(now, now);
+
+    if bomb {
+        action_state.press(&PlayerAction::Bomb);
+    } else {
+        action_state.release(&PlayerAction::Bomb);
+    }
+    action_state.set_axis_pair(&PlayerAction::Move, movement);
+}
+
+/// Add `Rollback` to the level entities that can change during a round, so that they are restored
+/// along with the players and bombs. These are sorted so that they are added in the same order on
+/// every peer.
+fn add_level_rollback(
+    mut commands: Commands,
+    tiles: Query<(Entity, &Parent, &GridCoords), Added<GridCoords>>,
+    level_entities: Query<
+        (Entity, &GridCoords),
+        (Added<GridCoords>, Or<(With<Door>, With<Switch>)>),
+    >,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+) {
+    let mut to_add = tiles
+        .iter()
+        .filter(|(_, parent, _)| {
+            ldtk_layer_meta_q
+                .get(***parent)
+                .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+        })
+        .map(|(entity, _, coords)| (entity, *coords))
+        .chain(
+            level_entities
+                .iter()
+                .map(|(entity, coords)| (entity, *coords)),
+        )
+        .collect::<Vec<_>>();
+    to_add.sort_by_key(|(_, coords)| (coords.x, coords.y));
+
+    for (entity, _) in to_add {
+        commands.entity(entity).add_rollback();
+    }
+}
+
+fn checksum_transform(transform: &Transform) -> u64 {
+    let mut hasher = checksum_hasher();
+    // The Z value is not part of the gameplay state, as it is only set for sorting sprites.
+    transform.translation.x.to_bits().hash(&mut hasher);
+    transform.translation.y.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn log_session_events(mut session: ResMut<Session<NetplayConfig>>) {
+    let Session::P2P(session) = &mut *session else {
+        return;
+    };
+
+    for event in session.events() {
+        match event {
+            GgrsEvent::DesyncDetected { frame, .. } => {
+                error!("desync detected on frame {frame}");
+            }
+            _ => info!("netplay event: {event:?}"),
+        }
+    }
+}
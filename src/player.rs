@@ -1,61 +1,127 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::AddRollbackCommandExtension;
 use leafwing_input_manager::prelude::*;
 
 use std::cmp::Ordering;
 
 use bevy::sprite::Anchor;
 use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-    bomb::{Bomb, CountBombs},
-    ldtk::ToGrid,
+    audio::PlaySfx,
+    bomb::{Bomb, BombSet, CountBombs, Explosion},
+    bot::Bot,
+    config::Config,
+    ldtk::{Blocker, Destroyed, TileSize, ToGrid, ToWorld},
+    netplay::Gameplay,
+    round::Round,
+    rules::LevelRules,
+    teleporter::Teleportable,
+    tide::{flooded_tiles, WADING_SPEED},
     z_sort::{ZSort, PLAYER_Z},
     GameState,
 };
 
 pub struct PlayerPlugin;
 
-const SPEED: f32 = 125.0;
+/// The initial speed of a player pushed back by an explosion.
+const KNOCKBACK_SPEED: f32 = 250.0;
+/// The rate at which knockback decays exponentially, per second.
+const KNOCKBACK_DECAY: f32 = 8.0;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(PreStartup, load_graphics)
             .insert_resource(CountPlayers(4))
             .add_plugins(InputManagerPlugin::<PlayerAction>::default())
+            .add_event::<PlayerEliminated>()
             .add_systems(OnEnter(GameState::InGame), spawn_players)
+            .add_systems(OnExit(GameState::InGame), despawn_players)
             .add_systems(
-                Update,
+                Gameplay,
                 (
-                    (movement_input, player_collisions, update_position).chain(),
-                    animate_player,
+                    eliminate_players,
+                    (
+                        explosion_knockback,
+                        movement_input,
+                        apply_knockback,
+                        player_collisions,
+                        update_position,
+                    )
+                        .chain()
+                        .after(BombSet),
                 )
                     .run_if(in_state(GameState::InGame)),
-            );
+            )
+            .add_systems(Update, animate_player.run_if(in_state(GameState::InGame)));
     }
 }
 
-/// Marker component for a Player
-#[derive(Component, Debug)]
-pub struct Player;
+/// Component for a Player, containing the index of its slot.
+#[derive(Component, Debug, Clone, Hash)]
+pub struct Player(pub usize);
+
+/// Marker component for a player who has been eliminated from the round, for example by being
+/// caught in an explosion. The player is despawned by `eliminate_players`.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Eliminated {
+    /// The index of the player responsible, if any. This may be the eliminated player.
+    pub killer: Option<usize>,
+}
+
+/// Event sent when a player is eliminated from the round.
+#[derive(Event, Debug)]
+pub struct PlayerEliminated {
+    pub player: usize,
+    pub killer: Option<usize>,
+}
 
 /// Linear velocity. Right now only for Player.
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Velocity(Vec2);
 
-#[derive(Component, Default, Debug)]
+/// Decaying velocity from the blast wave of a nearby explosion, in pixels per second.
+#[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Knockback(Vec2);
+
+#[derive(Component, Default, Debug, Clone)]
 pub struct PlayerAnimator {
     /// Used to determine if the player's sprite should flip on the Y axis. This is only updated
     /// when the sprite flips.
     pub prev_x_velocity_sign: f32,
 }
 
+/// Who controls a player slot.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayerController {
+    #[default]
+    Human,
+    Bot,
+    /// Controlled by the inputs of a netplay session, whether the player is local or remote, or by
+    /// a replay. This is only set when starting netplay or playback, so it can't be used in the
+    /// config file.
+    #[serde(skip)]
+    Network,
+}
+
+/// The size of a frame of the player sprite sheets in pixels, drawn for the default [`TileSize`].
+const PLAYER_SPRITE_SIZE: f32 = 64.0;
+
+/// The offset of a player's `Transform` from the center of the tile they are standing on when
+/// they spawn, a quarter of a tile down so their feet are in the middle of it.
+pub fn spawn_offset(tile_size: TileSize) -> Vec2 {
+    Vec2::new(0.0, -tile_size.0 / 4.0)
+}
+
 /// The number of players that will be spawned during setup.
 #[derive(Resource)]
-pub struct CountPlayers(usize);
+pub struct CountPlayers(pub usize);
 
 /// Spawns the players in their correct spawn points up to the `CountPlayers` resource, which
-/// should never exceed 4.
+/// should never exceed 4. Each player is controlled by a human or a bot according to the `players`
+/// setting of the [`Config`].
 /// Player 1 - Fishy
 /// Player 2 - Pescy
 /// Player 3 - Sharky
@@ -65,6 +131,8 @@ fn spawn_players(
     textures: Res<PlayerSheets>,
     spawn_points: Query<(&Transform, &EntityInstance)>,
     count_players: Res<CountPlayers>,
+    config: Res<Config>,
+    tile_size: Res<TileSize>,
 ) {
     for i in 0..count_players.0 {
         let player_name = format!("Player_{}", i + 1);
@@ -75,10 +143,10 @@ fn spawn_players(
             .map(|(transform, _)| transform.translation.truncate())
             .next()
             .unwrap_or_else(|| panic!("no spawn point found for player: {player_name}"))
-            + Vec2::Y * -8.0;
+            + spawn_offset(*tile_size);
 
-        commands.spawn((
-            Player,
+        let mut player = commands.spawn((
+            Player(i),
             Sprite {
                 image: textures
                     .0
@@ -88,55 +156,92 @@ fn spawn_players(
                 texture_atlas: Some(textures.1.clone().into()),
                 flip_x: i % 2 != 0,
                 anchor: Anchor::BottomCenter,
+                custom_size: Some(Vec2::splat(PLAYER_SPRITE_SIZE * tile_size.scale())),
                 ..default()
             },
             Transform::from_translation(translation.extend(PLAYER_Z)),
             Velocity::default(),
+            Knockback::default(),
             PlayerAnimator::default(),
-            CollisionBounds {
-                x: (-8.0, 8.0),
-                y: (0.0, 8.0),
-            },
+            CollisionBounds::player(*tile_size),
             CountBombs::default(),
-            // For testing purposes, all of the keys/controllers are hardcoded and assigned to the
-            // same players each time.
-            InputManagerBundle::<PlayerAction> {
-                input_map: match i {
-                    0 => InputMap::new([(PlayerAction::Bomb, KeyCode::Space)]).with_dual_axis(
-                        PlayerAction::Move,
-                        VirtualDPad::new(
-                            KeyCode::KeyW,
-                            KeyCode::KeyS,
-                            KeyCode::KeyA,
-                            KeyCode::KeyD,
-                        ),
-                    ),
-                    1 => InputMap::new([(PlayerAction::Bomb, KeyCode::ShiftRight)]).with_dual_axis(
-                        PlayerAction::Move,
-                        VirtualDPad::new(
-                            KeyCode::ArrowUp,
-                            KeyCode::ArrowDown,
-                            KeyCode::ArrowLeft,
-                            KeyCode::ArrowRight,
-                        ),
-                    ),
-                    // TODO: Since bevy 0.15 introduced "gamepads-as-entities", we will have to add
-                    // some gamepad registering logic.
-                    //
-                    // See: https://github.com/Leafwing-Studios/leafwing-input-manager/blob/main/examples/register_gamepads.rs
-                    2 => InputMap::new([(PlayerAction::Bomb, GamepadButton::East)])
-                        .with_dual_axis(PlayerAction::Move, GamepadStick::LEFT),
-                    //.set_gamepad(Gamepad { id: 0 })
-                    3 => InputMap::new([(PlayerAction::Bomb, GamepadButton::East)])
-                        .with_dual_axis(PlayerAction::Move, GamepadStick::LEFT),
-                    //.set_gamepad(Gamepad { id: 1 })
-                    _ => panic!("no input map for player: {player_name}"),
-                },
-                ..default()
-            },
+            Teleportable::new(translation.to_grid(*tile_size)),
             ZSort(PLAYER_Z),
             Name::new(player_name),
         ));
+        player.add_rollback();
+
+        match config.players.get(i).copied().unwrap_or_default() {
+            PlayerController::Human => {
+                player.insert(InputManagerBundle::<PlayerAction> {
+                    input_map: input_map(i),
+                    ..default()
+                });
+            }
+            PlayerController::Bot => {
+                // Bots have no `InputMap`, so their `ActionState` is only driven by the bot AI.
+                let difficulty = config.bot_difficulty.get(i).copied().unwrap_or_default();
+                player.insert((ActionState::<PlayerAction>::default(), Bot::new(difficulty)));
+            }
+            PlayerController::Network => {
+                // The `ActionState` is set by the netplay or replay module.
+                player.insert(ActionState::<PlayerAction>::default());
+            }
+        }
+    }
+}
+
+/// Despawn players marked as [`Eliminated`].
+fn eliminate_players(
+    mut commands: Commands,
+    players: Query<(Entity, &Player, &Eliminated), Added<Eliminated>>,
+    mut ev_eliminated: EventWriter<PlayerEliminated>,
+    mut ev_sfx: EventWriter<PlaySfx>,
+) {
+    for (entity, player, eliminated) in players.iter() {
+        ev_sfx.send(PlaySfx::PlayerDeath);
+        ev_eliminated.send(PlayerEliminated {
+            player: player.0,
+            killer: eliminated.killer,
+        });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn despawn_players(mut commands: Commands, players: Query<Entity, With<Player>>) {
+    players
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
+
+/// For testing purposes, all of the keys/controllers are hardcoded and assigned to the same players
+/// each time.
+pub fn input_map(player: usize) -> InputMap<PlayerAction> {
+    match player {
+        0 => InputMap::new([(PlayerAction::Bomb, KeyCode::Space)]).with_dual_axis(
+            PlayerAction::Move,
+            VirtualDPad::new(KeyCode::KeyW, KeyCode::KeyS, KeyCode::KeyA, KeyCode::KeyD),
+        ),
+        1 => InputMap::new([(PlayerAction::Bomb, KeyCode::ShiftRight)]).with_dual_axis(
+            PlayerAction::Move,
+            VirtualDPad::new(
+                KeyCode::ArrowUp,
+                KeyCode::ArrowDown,
+                KeyCode::ArrowLeft,
+                KeyCode::ArrowRight,
+            ),
+        ),
+        // TODO: Since bevy 0.15 introduced "gamepads-as-entities", we will have to add some gamepad
+        // registering logic.
+        //
+        // See: https://github.com/Leafwing-Studios/leafwing-input-manager/blob/main/examples/register_gamepads.rs
+        2 => InputMap::new([(PlayerAction::Bomb, GamepadButton::East)])
+            .with_dual_axis(PlayerAction::Move, GamepadStick::LEFT),
+        //.set_gamepad(Gamepad { id: 0 })
+        3 => InputMap::new([(PlayerAction::Bomb, GamepadButton::East)])
+            .with_dual_axis(PlayerAction::Move, GamepadStick::LEFT),
+        //.set_gamepad(Gamepad { id: 1 })
+        _ => panic!("no input map for player: Player_{}", player + 1),
     }
 }
 
@@ -172,56 +277,155 @@ pub enum PlayerAction {
     Bomb,
 }
 
-/// Get input and update the `Velocity` component of `Player`.
+/// Get input and update the `Velocity` component of `Player`. Players standing on a flooded tile
+/// wade through the tide at [`WADING_SPEED`].
 fn movement_input(
-    mut players: Query<(&ActionState<PlayerAction>, &mut Velocity), With<Player>>,
+    mut players: Query<(&ActionState<PlayerAction>, &Transform, &mut Velocity), With<Player>>,
+    tiles: Query<(&Parent, &GridCoords)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    round: Res<Round>,
+    rules: Res<LevelRules>,
     time: Res<Time>,
+    tile_size: Res<TileSize>,
 ) {
-    for (action_state, mut velocity) in players.iter_mut() {
+    let flooded = flooded_tiles(&tiles, &ldtk_layer_meta_q, &round, &rules);
+
+    for (action_state, transform, mut velocity) in players.iter_mut() {
+        let speed = if flooded.contains(&transform.translation.to_grid(*tile_size)) {
+            rules.0.player_speed * WADING_SPEED
+        } else {
+            rules.0.player_speed
+        };
         velocity.0 = action_state
             .axis_pair(&PlayerAction::Move)
             .normalize_or_zero()
-            * SPEED
+            * speed
             * time.delta_secs();
     }
 }
 
+/// Push back players within one tile of an explosion, but not inside it, if enabled by the
+/// [`LevelRules`].
+fn explosion_knockback(
+    mut players: Query<(&Transform, &mut Knockback), With<Player>>,
+    mut ev_explosion: EventReader<Explosion>,
+    rules: Res<LevelRules>,
+    tile_size: Res<TileSize>,
+) {
+    if !rules.0.explosion_knockback {
+        ev_explosion.clear();
+        return;
+    }
+
+    for explosion in ev_explosion.read() {
+        for (transform, mut knockback) in players.iter_mut() {
+            let coords = transform.translation.to_grid(*tile_size);
+            let near_miss = !explosion.tiles.contains(&coords)
+                && explosion.tiles.iter().any(|tile| {
+                    let displacement = coords - *tile;
+                    displacement.x.abs() + displacement.y.abs() == 1
+                });
+
+            if near_miss {
+                let direction = (transform.translation.truncate()
+                    - explosion.origin.to_world(*tile_size))
+                .normalize_or_zero();
+                knockback.0 += direction * KNOCKBACK_SPEED;
+            }
+        }
+    }
+}
+
+/// Add `Knockback` to the `Velocity` of players, and decay it.
+fn apply_knockback(
+    mut players: Query<(&mut Velocity, &mut Knockback), With<Player>>,
+    time: Res<Time>,
+) {
+    for (mut velocity, mut knockback) in players.iter_mut() {
+        velocity.0 += knockback.0 * time.delta_secs();
+
+        knockback.0 *= (-KNOCKBACK_DECAY * time.delta_secs()).exp();
+        if knockback.0.length_squared() < 1.0 {
+            knockback.0 = Vec2::ZERO;
+        }
+    }
+}
+
 /// Collision bounds from entity `Transform` of form (min, max)
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone)]
 pub struct CollisionBounds {
     pub x: (f32, f32),
     pub y: (f32, f32),
 }
 
-/// Detect player collisions with walls and bombs to restrict movement
+impl CollisionBounds {
+    /// The bounds of a player's feet: half a tile wide and a quarter of a tile high, so they fit
+    /// through corridors one tile wide.
+    pub fn player(tile_size: TileSize) -> Self {
+        let quarter = tile_size.0 / 4.0;
+        Self {
+            x: (-quarter, quarter),
+            y: (0.0, quarter),
+        }
+    }
+}
+
+/// Detect player collisions with walls, blockers and bombs to restrict movement. Players who would
+/// end up entirely on tiles of the `Hazards` layer, such as spikes or water, are eliminated.
+#[allow(clippy::type_complexity)]
 fn player_collisions(
-    mut players: Query<(&mut Velocity, &Transform, &CollisionBounds), With<Player>>,
-    tiles: Query<(&Parent, &GridCoords)>,
+    mut commands: Commands,
+    mut players: Query<
+        (
+            Entity,
+            &mut Velocity,
+            &Transform,
+            &CollisionBounds,
+            Has<Eliminated>,
+        ),
+        With<Player>,
+    >,
+    tiles: Query<(&Parent, &GridCoords), Without<Destroyed>>,
+    blockers: Query<&GridCoords, With<Blocker>>,
     bombs: Query<&Transform, With<Bomb>>,
     ldtk_layer_meta_q: Query<&LayerMetadata>,
+    tile_size: Res<TileSize>,
 ) {
     // Get the coords of tiles with a bomb on them
     let bomb_tiles = bombs
         .iter()
-        .map(|t| t.translation.to_grid())
+        .map(|t| t.translation.to_grid(*tile_size))
         .collect::<Vec<_>>();
 
+    let layer_identifier = |parent: &Parent, coords: &GridCoords| {
+        ldtk_layer_meta_q.get(**parent).map_or_else(
+            |_| {
+                warn!("LDtk tile not child of a layer with coords: {coords:?}");
+                None
+            },
+            |ldtk_layer| Some(ldtk_layer.identifier.as_str()),
+        )
+    };
+
     let unwalkable = tiles
         .iter()
         .filter(|(parent, coords)| {
             bomb_tiles.iter().any(|b| b == *coords)
-                || ldtk_layer_meta_q.get(***parent).map_or_else(
-                    |_| {
-                        warn!("LDtk tile not child of a layer with coords: {coords:?}");
-                        false
-                    },
-                    |ldtk_layer| matches!(ldtk_layer.identifier.as_str(), "Maze" | "Bombable"),
-                )
+                || matches!(layer_identifier(parent, coords), Some("Maze" | "Bombable"))
         })
         .map(|(_, coords)| coords)
+        .chain(blockers.iter())
         .collect::<Vec<_>>();
 
-    for (mut player_velocity, player_transform, player_bounds) in players.iter_mut() {
+    let hazards = tiles
+        .iter()
+        .filter(|(parent, coords)| layer_identifier(parent, coords) == Some("Hazards"))
+        .map(|(_, coords)| *coords)
+        .collect::<Vec<_>>();
+
+    for (entity, mut player_velocity, player_transform, player_bounds, eliminated) in
+        players.iter_mut()
+    {
         if unwalkable.iter().any(|coords| {
             let x = player_transform.translation.truncate() + Vec2::X * player_velocity.0.x;
             match player_velocity.0.x.partial_cmp(&0.0) {
@@ -231,8 +435,12 @@ fn player_collisions(
             }
             .iter()
             .cartesian_product([player_bounds.y.0, player_bounds.y.1].iter())
-            .map(|(bound_x, bound_y)| (x + Vec2::X * *bound_x + Vec2::Y * *bound_y).to_grid())
-            .filter(|player_coord| *player_coord != player_transform.translation.to_grid())
+            .map(|(bound_x, bound_y)| {
+                (x + Vec2::X * *bound_x + Vec2::Y * *bound_y).to_grid(*tile_size)
+            })
+            .filter(|player_coord| {
+                *player_coord != player_transform.translation.to_grid(*tile_size)
+            })
             .any(|player_coord| player_coord == **coords)
         }) {
             player_velocity.0.x = 0.0;
@@ -247,12 +455,29 @@ fn player_collisions(
             }
             .iter()
             .cartesian_product([player_bounds.x.0, player_bounds.x.1].iter())
-            .map(|(bound_y, bound_x)| (y + Vec2::X * *bound_x + Vec2::Y * *bound_y).to_grid())
-            .filter(|player_coord| *player_coord != player_transform.translation.to_grid())
+            .map(|(bound_y, bound_x)| {
+                (y + Vec2::X * *bound_x + Vec2::Y * *bound_y).to_grid(*tile_size)
+            })
+            .filter(|player_coord| {
+                *player_coord != player_transform.translation.to_grid(*tile_size)
+            })
             .any(|player_coord| player_coord == **coords)
         }) {
             player_velocity.0.y = 0.0;
         }
+
+        let position = player_transform.translation.truncate() + player_velocity.0;
+        let on_hazard = [player_bounds.x.0, player_bounds.x.1]
+            .iter()
+            .cartesian_product([player_bounds.y.0, player_bounds.y.1].iter())
+            .all(|(bound_x, bound_y)| {
+                hazards.contains(&(position + Vec2::new(*bound_x, *bound_y)).to_grid(*tile_size))
+            });
+        if on_hazard && !eliminated {
+            commands
+                .entity(entity)
+                .try_insert(Eliminated { killer: None });
+        }
     }
 }
 
@@ -293,3 +518,92 @@ fn load_graphics(
         layout,
     ));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arena::spawn_layout,
+        ascii_map::{spawn_test_map, AsciiMap},
+    };
+
+    const MAP: &str = "
+#####
+#1.4#
+#.x.#
+#3.2#
+#####
+";
+
+    fn spawn_player(app: &mut App, coords: GridCoords, velocity: Vec2) -> Entity {
+        let tile_size = *app.world().resource::<TileSize>();
+        app.world_mut()
+            .spawn((
+                Player(0),
+                Transform::from_translation(coords.to_world(tile_size).extend(PLAYER_Z)),
+                Velocity(velocity * tile_size.scale()),
+                CollisionBounds::player(tile_size),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn players_collide_with_walls_and_bombable_tiles() {
+        for tile_size in [TileSize(16.0), TileSize::default(), TileSize(48.0)] {
+            let mut app = App::new();
+            app.add_systems(Update, player_collisions);
+            spawn_test_map(app.world_mut(), MAP);
+            app.insert_resource(tile_size);
+
+            let into_wall = spawn_player(&mut app, GridCoords::new(1, 2), Vec2::new(-20.0, 0.0));
+            let into_bombable =
+                spawn_player(&mut app, GridCoords::new(3, 2), Vec2::new(-20.0, 0.0));
+            let into_floor = spawn_player(&mut app, GridCoords::new(3, 2), Vec2::new(0.0, -20.0));
+            // Between the left wall and the bombable tile in the middle.
+            let into_corridor = spawn_player(&mut app, GridCoords::new(1, 1), Vec2::new(0.0, 20.0));
+            app.update();
+
+            let velocity = |player| app.world().get::<Velocity>(player).unwrap().0;
+            assert_eq!(velocity(into_wall), Vec2::ZERO, "{tile_size:?}");
+            assert_eq!(velocity(into_bombable), Vec2::ZERO, "{tile_size:?}");
+            assert_eq!(
+                velocity(into_floor),
+                Vec2::new(0.0, -20.0) * tile_size.scale(),
+                "{tile_size:?}"
+            );
+            assert_eq!(
+                velocity(into_corridor),
+                Vec2::new(0.0, 20.0) * tile_size.scale(),
+                "{tile_size:?}"
+            );
+            assert!(app.world().get::<Eliminated>(into_wall).is_none());
+        }
+    }
+
+    #[test]
+    fn spawned_layouts_only_have_tiles_on_layers() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .add_systems(Update, player_collisions);
+
+        let AsciiMap(layout) = AsciiMap::parse(MAP).unwrap();
+        let asset_server = app.world().resource::<AssetServer>().clone();
+        spawn_layout(&mut app.world_mut().commands(), &layout, &asset_server);
+        app.world_mut().flush();
+        spawn_player(&mut app, GridCoords::new(1, 2), Vec2::new(0.0, -20.0));
+        app.update();
+
+        // `player_collisions` and `update_bombs` warn about tiles which aren't on a layer.
+        let mut tiles = app.world_mut().query::<(&Parent, &GridCoords)>();
+        let mut layers = app.world_mut().query::<&LayerMetadata>();
+        let world = app.world();
+        assert_eq!(tiles.iter(world).count(), 5 * 5 + 16 + 1 + 4);
+        for (parent, coords) in tiles.iter(world) {
+            assert!(
+                layers.get(world, parent.get()).is_ok(),
+                "{coords:?} isn't on a layer"
+            );
+        }
+    }
+}
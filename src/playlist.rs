@@ -0,0 +1,260 @@
+//! Playlists rotate the level between rounds, rather than replaying the same level every round. The
+//! playlist is part of the [`Ruleset`](crate::rules::Ruleset), under the `[ruleset.playlist]` table
+//! of the config, for example:
+//!
+//! ```toml
+//! [ruleset.playlist]
+//! levels = { set = "small" }
+//! order = "random"
+//!
+//! [ruleset.playlist.sets]
+//! small = ["Level_0", "Level_2", "Generated_Arena", "maps/example.map"]
+//! ```
+//!
+//! The next level is chosen when a round is over, using the [`GameRng`](crate::GameRng) so that
+//! every peer chooses the same level during netplay, and is loaded once the round over delay has
+//! passed.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+use crate::{
+    arena::GENERATED_LEVEL, ascii_map::is_ascii_map, ldtk::level_summaries, round::Round, GameState,
+};
+
+pub struct PlaylistPlugin;
+
+impl Plugin for PlaylistPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnExit(GameState::InGame), select_next_level);
+    }
+}
+
+/// Settings for which level is played after each round.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Playlist {
+    pub levels: PlaylistLevels,
+    pub order: PlaylistOrder,
+    /// Named lists of level identifiers, which can be chosen with [`PlaylistLevels::Set`].
+    pub sets: BTreeMap<String, Vec<String>>,
+}
+
+/// The levels that can be chosen for the next round.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistLevels {
+    /// Keep playing the level chosen at the start of the game.
+    #[default]
+    Selected,
+    /// Every level in `level.ldtk`.
+    All,
+    /// These level identifiers, in order.
+    List(Vec<String>),
+    /// The levels in the set with this name.
+    Set(String),
+}
+
+/// How the next level is chosen from the playlist.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistOrder {
+    /// The level after the current one, going back to the first level after the last one.
+    #[default]
+    InOrder,
+    /// Any level but the current one, at random.
+    Random,
+}
+
+impl Playlist {
+    /// The identifiers of the levels in the playlist which exist, in order, given the identifiers
+    /// of the levels in `level.ldtk`. Returns `None` if the level shouldn't change between rounds.
+    fn levels(&self, available: &[String]) -> Option<Vec<String>> {
+        let levels = match &self.levels {
+            PlaylistLevels::Selected => return None,
+            PlaylistLevels::All => return Some(available.to_vec()),
+            PlaylistLevels::List(levels) => levels,
+            PlaylistLevels::Set(name) => match self.sets.get(name) {
+                Some(levels) => levels,
+                None => {
+                    warn!("there is no playlist set called {name:?}, keeping the same level");
+                    return None;
+                }
+            },
+        };
+
+        let levels = levels
+            .iter()
+            .filter(|level| {
+                let exists =
+                    *level == GENERATED_LEVEL || is_ascii_map(level) || available.contains(level);
+                if !exists {
+                    warn!("skipping level {level:?} in the playlist, as it can't be played");
+                }
+                exists
+            })
+            .cloned()
+            .collect();
+        Some(levels)
+    }
+
+    /// Choose the level to play after `current`. Returns `None` if the level shouldn't change.
+    pub fn next_level(
+        &self,
+        project: &LdtkProject,
+        current: &str,
+        rng: &mut impl Rng,
+    ) -> Option<String> {
+        let available = level_summaries(project)
+            .into_iter()
+            .map(|summary| summary.identifier)
+            .collect::<Vec<_>>();
+        self.choose_level(&available, current, rng)
+    }
+
+    /// Choose the level to play after `current`, given the identifiers of the levels in
+    /// `level.ldtk`.
+    fn choose_level(
+        &self,
+        available: &[String],
+        current: &str,
+        rng: &mut impl Rng,
+    ) -> Option<String> {
+        let levels = self.levels(available)?;
+
+        match self.order {
+            PlaylistOrder::InOrder => {
+                let next = levels
+                    .iter()
+                    .position(|level| level == current)
+                    .map_or(0, |i| (i + 1) % levels.len().max(1));
+                levels.get(next).cloned()
+            }
+            PlaylistOrder::Random => {
+                // Only repeat the current level if it's the only one in the playlist.
+                let others = levels
+                    .iter()
+                    .filter(|level| *level != current)
+                    .collect::<Vec<_>>();
+                others
+                    .choose(rng)
+                    .map(|level| level.to_string())
+                    .or_else(|| levels.first().cloned())
+            }
+        }
+    }
+}
+
+/// The identifier of the selected level, whether it is selected by index or by identifier.
+pub fn selected_identifier(project: &LdtkProject, level_selection: &LevelSelection) -> String {
+    match level_selection {
+        LevelSelection::Identifier(identifier) => identifier.clone(),
+        _ => project
+            .find_raw_level_by_level_selection(level_selection)
+            .map(|level| level.identifier.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// Select the level chosen for the next round. This waits until the round has been left, as
+/// `bevy_ecs_ldtk` would otherwise spawn the new level into the world of the old one.
+fn select_next_level(round: Res<Round>, mut level_selection: ResMut<LevelSelection>) {
+    if let Some(level) = &round.next_level {
+        info!("moving on to level {level}");
+        *level_selection = LevelSelection::Identifier(level.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameRng;
+
+    fn available() -> Vec<String> {
+        ["Level_0", "Level_1", "Level_2"].map(String::from).to_vec()
+    }
+
+    fn playlist(levels: &[&str], order: PlaylistOrder) -> Playlist {
+        Playlist {
+            levels: PlaylistLevels::List(levels.iter().map(|level| level.to_string()).collect()),
+            order,
+            ..default()
+        }
+    }
+
+    fn next(playlist: &Playlist, current: &str) -> Option<String> {
+        playlist.choose_level(&available(), current, &mut GameRng::from_seed(0).0)
+    }
+
+    #[test]
+    fn in_order_wraps_around() {
+        let playlist = playlist(
+            &["Level_2", GENERATED_LEVEL, "Level_0"],
+            PlaylistOrder::InOrder,
+        );
+        assert_eq!(next(&playlist, "Level_2").as_deref(), Some(GENERATED_LEVEL));
+        assert_eq!(next(&playlist, GENERATED_LEVEL).as_deref(), Some("Level_0"));
+        assert_eq!(next(&playlist, "Level_0").as_deref(), Some("Level_2"));
+    }
+
+    #[test]
+    fn in_order_starts_from_the_first_level_when_the_current_one_is_missing() {
+        let playlist = playlist(&["Level_1", "Level_2"], PlaylistOrder::InOrder);
+        assert_eq!(next(&playlist, "Level_0").as_deref(), Some("Level_1"));
+    }
+
+    #[test]
+    fn random_never_repeats_the_current_level() {
+        let playlist = playlist(
+            &["Level_0", "Level_1", "maps/example.map"],
+            PlaylistOrder::Random,
+        );
+        for seed in 0..100 {
+            let next = playlist
+                .choose_level(&available(), "Level_1", &mut GameRng::from_seed(seed).0)
+                .unwrap();
+            assert!(next == "Level_0" || next == "maps/example.map", "{next}");
+        }
+    }
+
+    #[test]
+    fn a_single_level_is_repeated() {
+        for order in [PlaylistOrder::InOrder, PlaylistOrder::Random] {
+            let playlist = playlist(&["Level_1"], order);
+            assert_eq!(next(&playlist, "Level_1").as_deref(), Some("Level_1"));
+        }
+    }
+
+    #[test]
+    fn missing_levels_are_skipped() {
+        for order in [PlaylistOrder::InOrder, PlaylistOrder::Random] {
+            let some_missing = playlist(&["Level_9", "Level_1"], order);
+            assert_eq!(next(&some_missing, "Level_0").as_deref(), Some("Level_1"));
+
+            let all_missing = playlist(&["Level_9", "Missing"], order);
+            assert_eq!(next(&all_missing, "Level_0"), None);
+        }
+    }
+
+    #[test]
+    fn unknown_sets_keep_the_same_level() {
+        let mut playlist = Playlist {
+            levels: PlaylistLevels::Set("large".into()),
+            ..default()
+        };
+        playlist.sets.insert("small".into(), vec!["Level_1".into()]);
+        assert_eq!(next(&playlist, "Level_0"), None);
+
+        playlist.levels = PlaylistLevels::Set("small".into());
+        assert_eq!(next(&playlist, "Level_0").as_deref(), Some("Level_1"));
+    }
+
+    #[test]
+    fn selected_keeps_the_same_level() {
+        assert_eq!(next(&Playlist::default(), "Level_0"), None);
+    }
+}
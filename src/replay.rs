@@ -0,0 +1,428 @@
+//! Recording and playback of local games, so that funny rounds can be shared and bugs can be
+//! reproduced. A game is recorded with `--record <PATH>` and played back with `--replay <PATH>`.
+//!
+//! A replay holds the seed of the [`GameRng`], the level, the ruleset and every player's input on
+//! each frame that the [`Gameplay`] schedule ran, along with the length of that frame. Since the
+//! gameplay is deterministic, feeding these back in gives the same game. During playback, Space
+//! pauses, Right steps forward a frame while paused, and Up and Down change the speed.
+//!
+//! The file starts with [`MAGIC`] and the format [`VERSION`], followed by the length of a JSON
+//! header and the header itself. Each frame is then stored as its length in nanoseconds, a bit
+//! mask of the players whose input changed, and the new inputs of those players.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::Session;
+use leafwing_input_manager::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    config::Config,
+    ldtk::LevelId,
+    netplay::{set_action_state, Gameplay, NetplayConfig, NetplaySet, MAX_PLAYERS},
+    player::{CountPlayers, Player, PlayerAction, PlayerController},
+    rules::Ruleset,
+    GameRng, GameState,
+};
+
+pub struct ReplayPlugin;
+
+/// The bytes at the start of every replay file.
+pub const MAGIC: &[u8; 8] = b"BOMBYREP";
+
+/// The version of the replay format. This must be increased whenever the format or the gameplay
+/// changes in a way that would make older replays play out differently.
+pub const VERSION: u16 = 2;
+
+/// The fastest playback speed, in frames per rendered frame.
+const MAX_SPEED: usize = 8;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                start_playback.run_if(resource_added::<ReplayPlayer>),
+                (playback_controls, play_frames, update_playback_text)
+                    .chain()
+                    .run_if(resource_exists::<ReplayPlayer>),
+                record_frame
+                    .after(NetplaySet::Local)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<ReplayRecorder>)
+                    .run_if(not(resource_exists::<Session<NetplayConfig>>)),
+            ),
+        )
+        .add_systems(
+            OnExit(GameState::InGame),
+            save_recording.run_if(resource_exists::<ReplayRecorder>),
+        )
+        .add_systems(
+            Last,
+            save_recording
+                .run_if(resource_exists::<ReplayRecorder>)
+                .run_if(on_event::<AppExit>),
+        );
+    }
+}
+
+/// The settings a replay was recorded with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub seed: u64,
+    pub level: LevelId,
+    pub ruleset: Ruleset,
+    pub players: usize,
+}
+
+/// A player's input on one frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct PlayerInput {
+    bomb: bool,
+    movement: Vec2,
+}
+
+impl PlayerInput {
+    fn from_action_state(action_state: &ActionState<PlayerAction>) -> Self {
+        Self {
+            bomb: action_state.pressed(&PlayerAction::Bomb),
+            movement: action_state.axis_pair(&PlayerAction::Move),
+        }
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.bomb.into());
+        bytes.extend_from_slice(&self.movement.x.to_le_bytes());
+        bytes.extend_from_slice(&self.movement.y.to_le_bytes());
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            bomb: reader.take::<1>()?[0] != 0,
+            movement: Vec2::new(
+                f32::from_le_bytes(reader.take()?),
+                f32::from_le_bytes(reader.take()?),
+            ),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ReplayFrame {
+    delta: Duration,
+    /// The input of every player, in order.
+    inputs: Vec<PlayerInput>,
+}
+
+/// Resource for recording the local game, which is written to `path` after each round and when
+/// the game is closed.
+#[derive(Resource, Debug)]
+pub struct ReplayRecorder {
+    path: PathBuf,
+    seed: u64,
+    /// Set on the first recorded frame, once the level and players have been chosen.
+    header: Option<ReplayHeader>,
+    /// The encoded frames.
+    frames: Vec<u8>,
+    last_inputs: Vec<PlayerInput>,
+}
+
+impl ReplayRecorder {
+    /// Record to `path`. The [`GameRng`] must be seeded with `seed`.
+    pub fn new(path: PathBuf, seed: u64) -> Self {
+        Self {
+            path,
+            seed,
+            header: None,
+            frames: Vec::new(),
+            last_inputs: Vec::new(),
+        }
+    }
+}
+
+/// Resource for playing back a replay, which replaces the local gameplay while it exists.
+#[derive(Resource, Debug)]
+pub struct ReplayPlayer {
+    header: ReplayHeader,
+    frames: Vec<ReplayFrame>,
+    /// The index of the next frame to play.
+    next_frame: usize,
+    /// The number of frames to play each time the game is rendered.
+    speed: usize,
+    paused: bool,
+    /// Whether to play one frame while paused.
+    step: bool,
+}
+
+impl ReplayPlayer {
+    /// Load the replay at `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let mut reader = Reader(&bytes);
+
+        if reader.take::<8>()? != *MAGIC {
+            return Err("not a replay file".to_string());
+        }
+        let version = u16::from_le_bytes(reader.take()?);
+        if version != VERSION {
+            return Err(format!(
+                "replay is version {version}, but this game plays version {VERSION}"
+            ));
+        }
+
+        let header_len = u32::from_le_bytes(reader.take()?) as usize;
+        let header: ReplayHeader =
+            serde_json::from_slice(reader.take_slice(header_len)?).map_err(|e| e.to_string())?;
+        if header.players > MAX_PLAYERS {
+            return Err(format!("too many players: {}", header.players));
+        }
+
+        let mut frames = Vec::new();
+        let mut inputs = vec![PlayerInput::default(); header.players];
+        while !reader.0.is_empty() {
+            let delta = Duration::from_nanos(u32::from_le_bytes(reader.take()?).into());
+            let changed = reader.take::<1>()?[0];
+            for (i, input) in inputs.iter_mut().enumerate() {
+                if changed & (1 << i) != 0 {
+                    *input = PlayerInput::read(&mut reader)?;
+                }
+            }
+            frames.push(ReplayFrame {
+                delta,
+                inputs: inputs.clone(),
+            });
+        }
+
+        Ok(Self {
+            header,
+            frames,
+            next_frame: 0,
+            speed: 1,
+            paused: false,
+            step: false,
+        })
+    }
+}
+
+/// Reads the replay file from the front.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take_slice(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.0.len() < len {
+            return Err("unexpected end of file".to_string());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take_slice(N)?.try_into().unwrap())
+    }
+}
+
+fn record_frame(
+    mut recorder: ResMut<ReplayRecorder>,
+    players: Query<(&Player, &ActionState<PlayerAction>)>,
+    level_selection: Res<LevelSelection>,
+    ruleset: Res<Ruleset>,
+    count_players: Res<CountPlayers>,
+    time: Res<Time>,
+) {
+    let recorder = &mut *recorder;
+
+    if recorder.header.is_none() {
+        recorder.header = Some(ReplayHeader {
+            seed: recorder.seed,
+            level: LevelId::from_selection(&level_selection),
+            ruleset: ruleset.clone(),
+            players: count_players.0,
+        });
+        recorder.last_inputs = vec![PlayerInput::default(); count_players.0];
+    }
+
+    // Eliminated players are despawned, so their input just stays the same.
+    let mut inputs = recorder.last_inputs.clone();
+    for (player, action_state) in players.iter() {
+        if let Some(input) = inputs.get_mut(player.0) {
+            *input = PlayerInput::from_action_state(action_state);
+        }
+    }
+
+    let delta_nanos = u32::try_from(time.delta().as_nanos()).unwrap_or(u32::MAX);
+    recorder
+        .frames
+        .extend_from_slice(&delta_nanos.to_le_bytes());
+
+    let changed = inputs
+        .iter()
+        .zip(recorder.last_inputs.iter())
+        .enumerate()
+        .filter(|(_, (input, last))| input != last)
+        .fold(0u8, |mask, (i, _)| mask | 1 << i);
+    recorder.frames.push(changed);
+    for (i, input) in inputs.iter().enumerate() {
+        if changed & (1 << i) != 0 {
+            input.write(&mut recorder.frames);
+        }
+    }
+
+    recorder.last_inputs = inputs;
+}
+
+fn save_recording(recorder: Res<ReplayRecorder>) {
+    let Some(header) = &recorder.header else {
+        return;
+    };
+
+    let header = match serde_json::to_vec(header) {
+        Ok(header) => header,
+        Err(e) => {
+            warn!("failed to serialise replay header: {e}");
+            return;
+        }
+    };
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 6 + header.len() + recorder.frames.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&recorder.frames);
+
+    match fs::write(&recorder.path, bytes) {
+        Ok(()) => info!("saved replay to {:?}", recorder.path),
+        Err(e) => warn!("failed to save replay to {:?}: {e}", recorder.path),
+    }
+}
+
+/// Marker component for the text showing the state of the playback.
+#[derive(Component)]
+struct PlaybackText;
+
+fn start_playback(
+    mut commands: Commands,
+    player: Res<ReplayPlayer>,
+    mut config: ResMut<Config>,
+    mut count_players: ResMut<CountPlayers>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let header = &player.header;
+    info!(
+        "playing replay of {} frames: {header:?}",
+        player.frames.len()
+    );
+
+    commands.insert_resource(GameRng::from_seed(header.seed));
+    commands.insert_resource(header.level.to_selection());
+    commands.insert_resource(header.ruleset.clone());
+    config.players = vec![PlayerController::Network; header.players];
+    count_players.0 = header.players;
+
+    commands.spawn((
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..default()
+        },
+        PlaybackText,
+        Name::new("Playback text"),
+    ));
+
+    next_state.set(GameState::LoadingLevel);
+}
+
+fn playback_controls(mut player: ResMut<ReplayPlayer>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::Space) {
+        player.paused = !player.paused;
+    }
+    if keys.just_pressed(KeyCode::ArrowRight) && player.paused {
+        player.step = true;
+    }
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        player.speed = (player.speed * 2).min(MAX_SPEED);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        player.speed = (player.speed / 2).max(1);
+    }
+}
+
+/// Set every player's input from the replay and run the [`Gameplay`] schedule, as many times as
+/// the playback speed calls for. This stops early on a state change, since each recorded frame
+/// was played in a single frame of `Update`.
+fn play_frames(world: &mut World) {
+    if *world.resource::<State<GameState>>().get() != GameState::InGame {
+        return;
+    }
+
+    let player = world.resource::<ReplayPlayer>();
+    let count_frames = match (player.paused, player.step) {
+        (false, _) => player.speed,
+        (true, true) => 1,
+        (true, false) => 0,
+    };
+    world.resource_mut::<ReplayPlayer>().step = false;
+
+    let frame_time = *world.resource::<Time>();
+    let mut time = frame_time;
+    let mut players = world.query::<(&Player, &mut ActionState<PlayerAction>)>();
+
+    for _ in 0..count_frames {
+        if matches!(
+            world.resource::<NextState<GameState>>(),
+            NextState::Pending(_)
+        ) {
+            break;
+        }
+
+        let mut replay = world.resource_mut::<ReplayPlayer>();
+        let Some((delta, inputs)) = replay
+            .frames
+            .get(replay.next_frame)
+            .map(|frame| (frame.delta, frame.inputs.clone()))
+        else {
+            info!("replay finished");
+            replay.paused = true;
+            break;
+        };
+        replay.next_frame += 1;
+
+        for (player, mut action_state) in players.iter_mut(world) {
+            if let Some(input) = inputs.get(player.0) {
+                set_action_state(&mut action_state, input.bomb, input.movement);
+            }
+        }
+
+        time.advance_by(delta);
+        *world.resource_mut::<Time>() = time;
+        world.run_schedule(Gameplay);
+    }
+
+    // The rest of `Update` should see the real frame time.
+    *world.resource_mut::<Time>() = frame_time;
+}
+
+fn update_playback_text(
+    player: Res<ReplayPlayer>,
+    mut text: Single<&mut Text, With<PlaybackText>>,
+) {
+    let state = if player.paused {
+        "paused".to_string()
+    } else {
+        format!("x{}", player.speed)
+    };
+    text.0 = format!(
+        "Replay frame {}/{} ({state})",
+        player.next_frame,
+        player.frames.len()
+    );
+}
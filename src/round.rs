@@ -0,0 +1,122 @@
+//! A round ends when at most one player is left standing or the time runs out. After a short
+//! delay, the level is reloaded for the next round, or the next level in the
+//! [`Playlist`](crate::playlist::Playlist) is loaded.
+//!
+//! The [`Round`] is part of the gameplay state, so during netplay it is rolled back along with
+//! everything else. The next round is loaded by the netplay module once the end of the round has
+//! been confirmed, rather than from the [`Gameplay`] schedule.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use std::time::Duration;
+
+use crate::{
+    ldtk::LdtkProjectAsset,
+    netplay::{Gameplay, NetplaySet, NetplaySettings},
+    player::{Eliminated, Player},
+    playlist::selected_identifier,
+    rules::LevelRules,
+    GameRng, GameState,
+};
+
+pub struct RoundPlugin;
+
+/// How long to wait after a round is over before loading the next one.
+const ROUND_OVER_DELAY_SECS: f32 = 3.0;
+
+impl Plugin for RoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RoundOver>()
+            .init_resource::<Round>()
+            .add_systems(OnEnter(GameState::InGame), start_round)
+            .add_systems(Gameplay, update_round.run_if(in_state(GameState::InGame)))
+            .add_systems(
+                Update,
+                load_next_round
+                    .after(NetplaySet::Local)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(not(resource_exists::<NetplaySettings>)),
+            );
+    }
+}
+
+/// Resource tracking the current round.
+#[derive(Resource, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Round {
+    /// The time since the start of the round.
+    pub elapsed: Duration,
+    /// Started when the round is over. The next round is loaded when it finishes.
+    over_timer: Option<Timer>,
+    /// The level chosen from the playlist for the next round, once the round is over. This is
+    /// `None` if the same level is played again.
+    #[serde(default)]
+    pub next_level: Option<String>,
+}
+
+impl Round {
+    /// Whether the round is over and the round over delay has passed, so the next round can start.
+    pub fn is_finished(&self) -> bool {
+        self.over_timer.as_ref().is_some_and(Timer::finished)
+    }
+}
+
+/// Event sent when a round is over.
+#[derive(Event, Debug)]
+pub struct RoundOver {
+    /// The index of the last player standing, or `None` if the round is a draw.
+    pub winner: Option<usize>,
+    pub duration: Duration,
+}
+
+fn start_round(mut round: ResMut<Round>) {
+    *round = Round::default();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_round(
+    mut round: ResMut<Round>,
+    players: Query<&Player, Without<Eliminated>>,
+    rules: Res<LevelRules>,
+    time: Res<Time>,
+    level_selection: Res<LevelSelection>,
+    project: Res<LdtkProjectAsset>,
+    ldtk_assets: Res<Assets<LdtkProject>>,
+    mut rng: ResMut<GameRng>,
+    mut ev_round_over: EventWriter<RoundOver>,
+) {
+    if let Some(timer) = round.over_timer.as_mut() {
+        timer.tick(time.delta());
+        return;
+    }
+
+    round.elapsed += time.delta();
+
+    let out_of_time = rules
+        .0
+        .round_time_secs
+        .is_some_and(|round_time| round.elapsed.as_secs_f32() >= round_time);
+    if players.iter().count() <= 1 || out_of_time {
+        let winner = players.get_single().ok().map(|player| player.0);
+        info!("round over, winner: {winner:?}");
+
+        ev_round_over.send(RoundOver {
+            winner,
+            duration: round.elapsed,
+        });
+        round.next_level = ldtk_assets.get(&project.0).and_then(|project| {
+            let current = selected_identifier(project, &level_selection);
+            rules.0.playlist.next_level(project, &current, &mut rng.0)
+        });
+        round.over_timer = Some(Timer::from_seconds(ROUND_OVER_DELAY_SECS, TimerMode::Once));
+    }
+}
+
+/// Load the next round once this one is finished. During netplay this is done by the netplay
+/// module instead, as the round may turn out not to be finished after a rollback.
+fn load_next_round(round: Res<Round>, mut next_state: ResMut<NextState<GameState>>) {
+    if round.is_finished() {
+        next_state.set(GameState::LoadingLevel);
+    }
+}
@@ -0,0 +1,198 @@
+//! Gameplay rules which can be changed without changing the code, so that we can have both casual
+//! and competitive settings. The ruleset is loaded as part of the [`Config`](crate::config::Config)
+//! under the `[ruleset]` table.
+//!
+//! Levels can override some of the rules with custom level fields in LDtk, so that a level can have
+//! its own twist without any code. These fields are all optional:
+//!
+//! | Field         | Type   | Rule                |
+//! | ------------- | ------ | ------------------- |
+//! | `bomb_timer`  | Float  | `bomb_timer_secs`   |
+//! | `max_bombs`   | Int    | `max_bombs`         |
+//! | `blast_range` | Int    | `blast_range`       |
+//! | `speed`       | Float  | `player_speed`      |
+//! | `round_time`  | Float  | `round_time_secs`   |
+//! | `music_track` | String | `fight_music`       |
+//! | `tide_period` | Float  | `tide_period_secs`  |
+//! | `tide_flood`  | Float  | `tide_flood_secs`   |
+//!
+//! There is no `powerup_density` field, as there are no power-ups to place yet. A level which sets
+//! it gets a warning, rather than it being silently ignored.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::Level, prelude::*};
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::fmt::Display;
+
+use crate::{arena::ArenaSettings, ldtk::LdtkProjectAsset, playlist::Playlist, GameState};
+
+pub struct RulesPlugin;
+
+impl Plugin for RulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelRules>()
+            .add_systems(OnExit(GameState::LoadingLevel), apply_level_rules);
+    }
+}
+
+/// Resource containing the rules for the current game.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ruleset {
+    /// Whether the blast wave of an explosion pushes back players who are just outside of it.
+    pub explosion_knockback: bool,
+    /// The length of a round in seconds. If more than one player is left when the time runs out,
+    /// the round is a draw. Rounds have no time limit if this isn't set.
+    pub round_time_secs: Option<f32>,
+    /// How long a bomb takes to explode.
+    pub bomb_timer_secs: f32,
+    /// The number of bombs each player can have placed at once.
+    pub max_bombs: u8,
+    /// The number of tiles an explosion reaches in each orthogonal direction.
+    pub blast_range: i32,
+    /// How fast players walk, in pixels per second.
+    pub player_speed: f32,
+    /// The path of the music to play during a round, relative to the assets folder. A random fight
+    /// track is played if this isn't set.
+    pub fight_music: Option<String>,
+    /// How often the tide comes in, on levels with a `Tide` layer. The tide never comes in if this
+    /// is zero.
+    pub tide_period_secs: f32,
+    /// How long the tide stays in for.
+    pub tide_flood_secs: f32,
+    /// How to generate the arena, when a generated arena is played instead of an LDtk level.
+    pub arena: ArenaSettings,
+    /// Which level to play after each round.
+    pub playlist: Playlist,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self {
+            explosion_knockback: false,
+            round_time_secs: None,
+            bomb_timer_secs: 1.5,
+            max_bombs: 2,
+            blast_range: 1,
+            player_speed: 125.0,
+            fight_music: None,
+            tide_period_secs: 20.0,
+            tide_flood_secs: 6.0,
+            arena: ArenaSettings::default(),
+            playlist: Playlist::default(),
+        }
+    }
+}
+
+impl Ruleset {
+    /// Replace any rules which are out of range with their defaults, such as a bomb timer which
+    /// isn't positive.
+    pub fn validated(mut self) -> Self {
+        let default = Self::default();
+        self.round_time_secs = self
+            .round_time_secs
+            .and_then(|secs| check_rule("round_time_secs", secs, is_positive));
+        self.bomb_timer_secs = check_rule("bomb_timer_secs", self.bomb_timer_secs, is_positive)
+            .unwrap_or(default.bomb_timer_secs);
+        self.blast_range = check_rule("blast_range", self.blast_range, |range| *range >= 0)
+            .unwrap_or(default.blast_range);
+        self.player_speed = check_rule("player_speed", self.player_speed, is_positive)
+            .unwrap_or(default.player_speed);
+        self.tide_period_secs =
+            check_rule("tide_period_secs", self.tide_period_secs, is_non_negative)
+                .unwrap_or(default.tide_period_secs);
+        self.tide_flood_secs = check_rule("tide_flood_secs", self.tide_flood_secs, is_non_negative)
+            .unwrap_or(default.tide_flood_secs);
+        self
+    }
+
+    /// Override the rules set by the custom fields of an LDtk level. Fields which are missing, null
+    /// or out of range are ignored.
+    pub fn with_level_fields(mut self, level: &Level) -> Self {
+        let float_field = |field: &str, is_valid: fn(&f32) -> bool| {
+            let value = *level.get_float_field(field).ok()?;
+            check_rule(field, value, is_valid)
+        };
+        let int_field = |field: &str, is_valid: fn(&i32) -> bool| {
+            let value = *level.get_int_field(field).ok()?;
+            check_rule(field, value, is_valid)
+        };
+
+        if let Some(bomb_timer) = float_field("bomb_timer", is_positive) {
+            self.bomb_timer_secs = bomb_timer;
+        }
+        if let Some(max_bombs) = int_field("max_bombs", |max| u8::try_from(*max).is_ok()) {
+            self.max_bombs = max_bombs as u8;
+        }
+        if let Some(blast_range) = int_field("blast_range", |range| *range >= 0) {
+            self.blast_range = blast_range;
+        }
+        if let Some(speed) = float_field("speed", is_positive) {
+            self.player_speed = speed;
+        }
+        if let Some(round_time) = float_field("round_time", is_positive) {
+            self.round_time_secs = Some(round_time);
+        }
+        if let Ok(music_track) = level.get_string_field("music_track") {
+            self.fight_music = Some(music_track.clone());
+        }
+        if let Some(tide_period) = float_field("tide_period", is_non_negative) {
+            self.tide_period_secs = tide_period;
+        }
+        if let Some(tide_flood) = float_field("tide_flood", is_non_negative) {
+            self.tide_flood_secs = tide_flood;
+        }
+        if level.get_float_field("powerup_density").is_ok() {
+            warn!(
+                "ignoring powerup_density of level {}, as there are no power-ups yet",
+                level.identifier
+            );
+        }
+        self
+    }
+}
+
+/// Check that a rule is in range, warning about it and returning `None` if it isn't.
+fn check_rule<T: Display>(rule: &str, value: T, is_valid: impl FnOnce(&T) -> bool) -> Option<T> {
+    if is_valid(&value) {
+        Some(value)
+    } else {
+        warn!("ignoring {rule} of {value}, which is out of range");
+        None
+    }
+}
+
+fn is_positive(value: &f32) -> bool {
+    value.is_finite() && *value > 0.0
+}
+
+fn is_non_negative(value: &f32) -> bool {
+    value.is_finite() && *value >= 0.0
+}
+
+/// Resource containing the rules for the level being played, which is the [`Ruleset`] with the
+/// level's overrides. Gameplay systems should read this rather than the `Ruleset`.
+#[derive(Resource, Default, Debug, Clone)]
+pub struct LevelRules(pub Ruleset);
+
+/// Set the [`LevelRules`] for the level that has just loaded.
+fn apply_level_rules(
+    mut level_rules: ResMut<LevelRules>,
+    ruleset: Res<Ruleset>,
+    level_selection: Res<LevelSelection>,
+    project: Res<LdtkProjectAsset>,
+    ldtk_assets: Res<Assets<LdtkProject>>,
+) {
+    let level = ldtk_assets
+        .get(&project.0)
+        .and_then(|project| project.find_raw_level_by_level_selection(&level_selection));
+
+    // Generated arenas aren't in the LDtk project, so they just use the ruleset.
+    level_rules.0 = match level {
+        Some(level) => ruleset.clone().with_level_fields(level),
+        None => ruleset.clone(),
+    };
+    debug!("rules for this level: {:?}", level_rules.0);
+}
@@ -0,0 +1,532 @@
+//! Dedicated server, run with the `bomby-server` binary. The server runs the gameplay in a
+//! [`headless_app`], so it doesn't need a window, audio or a GPU. It loads `level.ldtk` from the
+//! assets folder as usual, and starts once enough clients have joined.
+//!
+//! Clients connect with `--connect <ADDR>`. Each frame, every client sends its input and the
+//! server sends back the state of the round, which the client shows without simulating anything
+//! itself. Messages are sent over UDP in a compact binary format, and are kept small enough to fit
+//! in a single packet on almost any network. The state of a frame is usually small enough to send
+//! in one message, but is split into as many [`ServerMessage::FramePart`]s as it needs.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::TileTextureIndex;
+use bincode::Options;
+use leafwing_input_manager::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    arena::ArenaSeed,
+    armour::Armour,
+    bomb::{Bomb, Explosion},
+    cli::ServerArgs,
+    config::Config,
+    door::Door,
+    ldtk::{Destroyed, GridCoordsDef, LevelId},
+    netplay::{apply_input, NetplaySet},
+    player::{CountPlayers, Player, PlayerAction, PlayerController, Velocity},
+    rules::Ruleset,
+    sim::{headless_app, TIMESTEP_SECS},
+    GameState,
+};
+
+pub struct ServerPlugin;
+
+pub const DEFAULT_PORT: u16 = 7979;
+
+/// How long to wait for a message from a client before dropping it.
+const CLIENT_TIMEOUT_SECS: f32 = 5.0;
+
+/// The largest message to send, which leaves room for the IP and UDP headers within the smallest
+/// MTU seen in practice, so that messages aren't fragmented or dropped on the way.
+const MAX_PACKET_SIZE: usize = 1200;
+
+/// The most bytes of a frame to send in each [`ServerMessage::FramePart`], leaving room for the
+/// rest of the message.
+const FRAME_PART_SIZE: usize = MAX_PACKET_SIZE - 32;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                receive_messages,
+                drop_clients,
+                start_game.run_if(in_state(GameState::PreLoad)),
+                apply_inputs,
+            )
+                .chain()
+                .before(NetplaySet::Local),
+        )
+        .add_systems(
+            Update,
+            send_frame
+                .after(NetplaySet::Local)
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnEnter(GameState::InGame), start_round);
+    }
+}
+
+/// Run the server described by `args`. This blocks until the server is stopped.
+pub fn run(mut config: Config, args: &ServerArgs) {
+    let seed = args.seed.or(config.seed).unwrap_or_else(rand::random);
+    let port = args.port.unwrap_or(DEFAULT_PORT);
+    let count_players = args.players;
+
+    config.players = vec![PlayerController::Network; count_players];
+    let mut app = headless_app(config, seed, Duration::from_secs_f64(TIMESTEP_SECS));
+
+    let socket = match bind(port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("failed to bind to port {port}: {e}");
+            return;
+        }
+    };
+
+    app.add_plugins(ServerPlugin)
+        .insert_resource(CountPlayers(count_players))
+        .insert_resource(Server {
+            socket,
+            clients: Vec::new(),
+            count_players,
+            round: 0,
+            frame: 0,
+        });
+
+    if let Some(level) = &args.level {
+        app.insert_resource(LevelSelection::Identifier(level.clone()));
+    }
+
+    info!("listening on port {port} for {count_players} players, with seed {seed}");
+    app.run();
+}
+
+fn bind(port: u16) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// A message from a client to the server.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// Ask for a player slot. This is repeated until the client is welcomed.
+    Join,
+    /// The client's input, encoded by [`encode_input`](crate::netplay::encode_input).
+    Input(u8),
+    Leave,
+}
+
+/// A message from the server to a client.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome {
+        /// The index of the client's player.
+        player: usize,
+        count_players: usize,
+        level: LevelId,
+        ruleset: Ruleset,
+    },
+    /// Every player slot is taken.
+    Full,
+    /// Part of an encoded [`FrameState`]. A frame can only be shown once every part has arrived.
+    FramePart {
+        /// The number of frames sent by the server, so that parts of different frames aren't mixed.
+        frame: u32,
+        part: u8,
+        parts: u8,
+        bytes: Vec<u8>,
+    },
+}
+
+/// The state of the round after a frame has been simulated.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FrameState {
+    /// The number of rounds started by the server, so that clients know when to reload the level.
+    pub round: u32,
+    /// The level being played, so that clients load the right level when the playlist moves on.
+    pub level: Option<LevelId>,
+    /// The seed of the level, if it is a generated arena, so that clients generate the same arena.
+    pub arena_seed: Option<u64>,
+    /// The players still in the round.
+    pub players: Vec<PlayerState>,
+    pub bombs: Vec<BombState>,
+    pub destroyed_tiles: Vec<Coords>,
+    /// The texture of each bombable tile with armour left.
+    pub armoured_tiles: Vec<(Coords, u32)>,
+    /// Each door, and whether it is open.
+    pub doors: Vec<(Coords, bool)>,
+    /// The explosions during this frame.
+    pub explosions: Vec<ExplosionState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub player: usize,
+    pub translation: Vec3,
+    pub velocity: Velocity,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BombState {
+    pub translation: Vec3,
+    pub bomb: Bomb,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExplosionState {
+    pub origin: Coords,
+    pub tiles: Vec<Coords>,
+    pub destroyed_tiles: Vec<Coords>,
+}
+
+impl From<&Explosion> for ExplosionState {
+    fn from(explosion: &Explosion) -> Self {
+        Self {
+            origin: Coords(explosion.origin),
+            tiles: explosion.tiles.iter().copied().map(Coords).collect(),
+            destroyed_tiles: explosion
+                .destroyed_tiles
+                .iter()
+                .copied()
+                .map(Coords)
+                .collect(),
+        }
+    }
+}
+
+impl From<&ExplosionState> for Explosion {
+    fn from(explosion: &ExplosionState) -> Self {
+        Self {
+            origin: explosion.origin.0,
+            tiles: explosion.tiles.iter().map(|coords| coords.0).collect(),
+            destroyed_tiles: explosion
+                .destroyed_tiles
+                .iter()
+                .map(|coords| coords.0)
+                .collect(),
+        }
+    }
+}
+
+/// `GridCoords` which can be sent in a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coords(#[serde(with = "GridCoordsDef")] pub GridCoords);
+
+/// Encode the state of a frame, and split it into messages which each fit in a single packet.
+fn split_frame(frame: u32, state: &FrameState) -> Result<Vec<ServerMessage>, String> {
+    let bytes = encoding().serialize(state).map_err(|e| e.to_string())?;
+    let chunks = bytes.chunks(FRAME_PART_SIZE);
+    let parts = u8::try_from(chunks.len())
+        .map_err(|_| format!("the frame is too big to send, at {} bytes", bytes.len()))?;
+
+    Ok(chunks
+        .enumerate()
+        .map(|(part, bytes)| ServerMessage::FramePart {
+            frame,
+            part: part as u8,
+            parts,
+            bytes: bytes.to_vec(),
+        })
+        .collect())
+}
+
+/// The parts of the newest frame received from the server, which are put back together once they
+/// have all arrived.
+#[derive(Debug, Default)]
+pub struct FrameParts {
+    /// The frame which the parts are from, or the last frame put back together.
+    frame: Option<u32>,
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl FrameParts {
+    /// Add part of a frame, returning the frame if it is now complete. Parts of frames older than
+    /// the current one are ignored, and a newer frame replaces any parts of the current one.
+    pub fn add(&mut self, frame: u32, part: u8, parts: u8, bytes: Vec<u8>) -> Option<FrameState> {
+        if part >= parts {
+            return None;
+        }
+        match self.frame {
+            Some(current) if frame < current => return None,
+            // Either the frame is already complete, or this part doesn't match the others.
+            Some(current) if frame == current && self.parts.len() != parts as usize => return None,
+            Some(current) if frame == current => {}
+            _ => {
+                self.frame = Some(frame);
+                self.parts = vec![None; parts as usize];
+            }
+        }
+
+        self.parts[part as usize] = Some(bytes);
+        if self.parts.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let bytes = self.parts.drain(..).flatten().flatten().collect::<Vec<_>>();
+        match encoding().deserialize(&bytes) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                debug!("ignoring invalid frame {frame}: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// The encoding of every message, which writes integers in as few bytes as they need.
+fn encoding() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+/// Send a message, unless it is too big for a single packet.
+pub fn send(socket: &UdpSocket, message: &impl serde::Serialize, addr: SocketAddr) {
+    let bytes = encoding()
+        .serialize(message)
+        .expect("failed to serialise message");
+    if bytes.len() > MAX_PACKET_SIZE {
+        warn!(
+            "not sending a message of {} bytes to {addr}, the limit is {MAX_PACKET_SIZE}",
+            bytes.len()
+        );
+        return;
+    }
+    if let Err(e) = socket.send_to(&bytes, addr) {
+        warn!("failed to send message to {addr}: {e}");
+    }
+}
+
+/// Read every waiting message from `socket`, ignoring anything which isn't a `T`.
+pub fn receive<T: DeserializeOwned>(socket: &UdpSocket) -> Vec<(T, SocketAddr)> {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    let mut messages = Vec::new();
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, src)) => match encoding().deserialize(&buf[..len]) {
+                Ok(message) => messages.push((message, src)),
+                Err(e) => debug!("ignoring invalid message from {src}: {e}"),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            // On some platforms, an earlier send to a closed port shows up as an error here.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                warn!("failed to receive message: {e}");
+                break;
+            }
+        }
+    }
+
+    messages
+}
+
+#[derive(Resource, Debug)]
+struct Server {
+    socket: UdpSocket,
+    clients: Vec<ConnectedClient>,
+    count_players: usize,
+    /// The number of rounds started so far.
+    round: u32,
+    /// The number of frames sent so far.
+    frame: u32,
+}
+
+#[derive(Debug)]
+struct ConnectedClient {
+    addr: SocketAddr,
+    player: usize,
+    /// The last input received from the client.
+    input: u8,
+    since_heard: f32,
+}
+
+fn receive_messages(
+    mut server: ResMut<Server>,
+    level_selection: Res<LevelSelection>,
+    ruleset: Res<Ruleset>,
+) {
+    let server = &mut *server;
+
+    for (message, addr) in receive::<ClientMessage>(&server.socket) {
+        let index = server.clients.iter().position(|client| client.addr == addr);
+        if let Some(index) = index {
+            server.clients[index].since_heard = 0.0;
+        }
+
+        match (message, index) {
+            (ClientMessage::Join, Some(index)) => {
+                let welcome = welcome(
+                    server.clients[index].player,
+                    server.count_players,
+                    &level_selection,
+                    &ruleset,
+                );
+                send(&server.socket, &welcome, addr);
+            }
+            (ClientMessage::Join, None) => {
+                let free_player = (0..server.count_players)
+                    .find(|player| server.clients.iter().all(|client| client.player != *player));
+                let Some(player) = free_player else {
+                    send(&server.socket, &ServerMessage::Full, addr);
+                    continue;
+                };
+
+                info!("{addr} joined as player {}", player + 1);
+                server.clients.push(ConnectedClient {
+                    addr,
+                    player,
+                    input: 0,
+                    since_heard: 0.0,
+                });
+                let welcome = welcome(player, server.count_players, &level_selection, &ruleset);
+                send(&server.socket, &welcome, addr);
+            }
+            (ClientMessage::Input(input), Some(index)) => {
+                server.clients[index].input = input;
+            }
+            (ClientMessage::Leave, Some(index)) => {
+                info!("{addr} left");
+                server.clients.remove(index);
+            }
+            (_, None) => debug!("ignoring message from unknown client {addr}"),
+        }
+    }
+}
+
+fn welcome(
+    player: usize,
+    count_players: usize,
+    level_selection: &LevelSelection,
+    ruleset: &Ruleset,
+) -> ServerMessage {
+    ServerMessage::Welcome {
+        player,
+        count_players,
+        level: LevelId::from_selection(level_selection),
+        ruleset: ruleset.clone(),
+    }
+}
+
+/// Drop clients which haven't been heard from in a while. Their players stand still until someone
+/// else joins in their place.
+fn drop_clients(mut server: ResMut<Server>, time: Res<Time>) {
+    server.clients.retain_mut(|client| {
+        client.since_heard += time.delta_secs();
+        let timed_out = client.since_heard > CLIENT_TIMEOUT_SECS;
+        if timed_out {
+            info!("{} timed out", client.addr);
+        }
+        !timed_out
+    });
+}
+
+/// Start the first round once every player slot is taken.
+fn start_game(server: Res<Server>, mut next_state: ResMut<NextState<GameState>>) {
+    if server.clients.len() == server.count_players {
+        info!("every player has joined, starting the game");
+        next_state.set(GameState::LoadingLevel);
+    }
+}
+
+fn start_round(mut server: ResMut<Server>) {
+    server.round += 1;
+}
+
+fn apply_inputs(
+    server: Res<Server>,
+    mut players: Query<(&Player, &mut ActionState<PlayerAction>)>,
+) {
+    for (player, mut action_state) in players.iter_mut() {
+        let input = server
+            .clients
+            .iter()
+            .find(|client| client.player == player.0)
+            .map_or(0, |client| client.input);
+        apply_input(&mut action_state, input);
+    }
+}
+
+/// Send the state of the round to every client.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn send_frame(
+    mut server: ResMut<Server>,
+    players: Query<(&Player, &Transform, &Velocity)>,
+    bombs: Query<(&Bomb, &Transform)>,
+    tiles: Query<(
+        &Parent,
+        &GridCoords,
+        &TileTextureIndex,
+        Has<Armour>,
+        Has<Destroyed>,
+    )>,
+    doors: Query<(&Door, &GridCoords)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    mut ev_explosion: EventReader<Explosion>,
+    level_selection: Res<LevelSelection>,
+    arena_seed: Option<Res<ArenaSeed>>,
+) {
+    let mut frame = FrameState {
+        round: server.round,
+        level: Some(LevelId::from_selection(&level_selection)),
+        arena_seed: arena_seed.map(|seed| seed.0),
+        ..default()
+    };
+
+    for (player, transform, velocity) in players.iter() {
+        frame.players.push(PlayerState {
+            player: player.0,
+            translation: transform.translation,
+            velocity: velocity.clone(),
+        });
+    }
+
+    for (bomb, transform) in bombs.iter() {
+        frame.bombs.push(BombState {
+            translation: transform.translation,
+            bomb: bomb.clone(),
+        });
+    }
+
+    for (_, coords, texture_index, armoured, destroyed) in tiles.iter().filter(|(parent, ..)| {
+        ldtk_layer_meta_q
+            .get(***parent)
+            .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+    }) {
+        if destroyed {
+            frame.destroyed_tiles.push(Coords(*coords));
+        } else if armoured {
+            frame
+                .armoured_tiles
+                .push((Coords(*coords), texture_index.0));
+        }
+    }
+
+    for (door, coords) in doors.iter() {
+        frame.doors.push((Coords(*coords), door.open));
+    }
+
+    frame.explosions = ev_explosion.read().map(ExplosionState::from).collect();
+
+    server.frame += 1;
+    let messages = match split_frame(server.frame, &frame) {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("failed to send frame {}: {e}", server.frame);
+            return;
+        }
+    };
+    for client in server.clients.iter() {
+        for message in messages.iter() {
+            send(&server.socket, message, client.addr);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Headless bot-vs-bot simulation, for evaluating level and rule changes without having to play
+//! them. Started with `--simulate N`, this runs the gameplay plugins in a [`headless_app`], as fast
+//! as possible with a fixed timestep and RNG seed. After N matches, the win rates, average round
+//! length and kill stats are written to a JSON or CSV file.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde_derive::Serialize;
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    render::{settings::WgpuSettings, RenderPlugin},
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+
+use crate::{
+    arena, armour, ascii_map, audio::PlaySfx, bomb, bot, camera::CameraTrauma, cli::Args,
+    config::Config, door, ldtk, netplay, player, player::PlayerController,
+    player::PlayerEliminated, playlist, round, round::RoundOver, rules, teleporter, tide, GameRng,
+    GameState,
+};
+
+/// The length of each simulated frame.
+pub const TIMESTEP_SECS: f64 = 1.0 / 60.0;
+
+/// The time limit for simulated rounds, unless the ruleset sets one, so that bots which can't reach
+/// each other don't play forever.
+const DEFAULT_ROUND_TIME_SECS: f32 = 180.0;
+
+const DEFAULT_OUTPUT: &str = "sim.json";
+
+/// Run the simulation described by `args`. This blocks until the simulation is finished.
+pub fn run(mut config: Config, args: &Args, seed: u64) {
+    let matches = args.simulate.unwrap_or_default();
+    let output = args.output.clone().unwrap_or(DEFAULT_OUTPUT.into());
+
+    if matches == 0 {
+        warn!("no matches to simulate");
+        return;
+    }
+
+    config.players = vec![PlayerController::Bot; 4];
+    config
+        .ruleset
+        .round_time_secs
+        .get_or_insert(DEFAULT_ROUND_TIME_SECS);
+    let stats = SimStats::new(matches, output, config.players.len());
+
+    let mut app = headless_app(config, seed, Duration::ZERO);
+    app.insert_resource(stats)
+        .add_systems(Startup, start_simulation)
+        .add_systems(Update, (record_eliminations, record_rounds).chain());
+
+    if let Some(level) = &args.level {
+        app.insert_resource(LevelSelection::Identifier(level.clone()));
+    }
+
+    info!("simulating {matches} matches with seed {seed}");
+    app.run();
+}
+
+/// Build an app with the gameplay plugins, but without a window, renderer or audio, so that it can
+/// run on a machine without a GPU. Each frame is simulated with a fixed timestep, waiting at least
+/// `wait` between frames.
+pub fn headless_app(config: Config, seed: u64, wait: Duration) -> App {
+    let mut app = App::new();
+    app.add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .set(RenderPlugin {
+                render_creation: WgpuSettings {
+                    backends: None,
+                    ..default()
+                }
+                .into(),
+                ..default()
+            })
+            .disable::<WinitPlugin>(),
+    )
+    .add_plugins(ScheduleRunnerPlugin::run_loop(wait))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        TIMESTEP_SECS,
+    )))
+    .init_state::<GameState>()
+    .insert_resource(config.ruleset.clone())
+    .insert_resource(config)
+    // These are normally added by the audio and camera plugins, but are sent by gameplay code.
+    .add_event::<PlaySfx>()
+    .add_event::<CameraTrauma>()
+    .add_plugins((
+        player::PlayerPlugin,
+        ldtk::BombyLdtkPlugin,
+        arena::ArenaPlugin,
+        ascii_map::AsciiMapPlugin,
+        bomb::BombPlugin,
+        teleporter::TeleporterPlugin,
+        door::DoorPlugin,
+        tide::TidePlugin,
+        armour::ArmourPlugin,
+        bot::BotPlugin,
+        round::RoundPlugin,
+        playlist::PlaylistPlugin,
+        rules::RulesPlugin,
+        netplay::NetplayPlugin,
+    ))
+    .insert_resource(GameRng::from_seed(seed));
+
+    app
+}
+
+fn start_simulation(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::LoadingLevel);
+}
+
+#[derive(Resource, Debug)]
+struct SimStats {
+    /// The number of matches to simulate.
+    matches: u32,
+    output: PathBuf,
+    report: SimReport,
+    total_round_secs: f32,
+}
+
+impl SimStats {
+    fn new(matches: u32, output: PathBuf, count_players: usize) -> Self {
+        let players = (0..count_players)
+            .map(|i| PlayerStats {
+                player: i + 1,
+                ..default()
+            })
+            .collect();
+
+        Self {
+            matches,
+            output,
+            report: SimReport {
+                players,
+                ..default()
+            },
+            total_round_secs: 0.0,
+        }
+    }
+
+    fn player(&mut self, index: usize) -> &mut PlayerStats {
+        let players = &mut self.report.players;
+        while players.len() <= index {
+            players.push(PlayerStats {
+                player: players.len() + 1,
+                ..default()
+            });
+        }
+        &mut players[index]
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+struct SimReport {
+    matches: u32,
+    draws: u32,
+    average_round_secs: f32,
+    players: Vec<PlayerStats>,
+}
+
+#[derive(Serialize, Default, Debug)]
+struct PlayerStats {
+    /// The player number, starting at 1.
+    player: usize,
+    wins: u32,
+    win_rate: f32,
+    kills: u32,
+    deaths: u32,
+    suicides: u32,
+}
+
+impl SimReport {
+    fn to_csv(&self) -> String {
+        let mut csv =
+            "player,matches,wins,win_rate,kills,deaths,suicides,draws,average_round_secs\n"
+                .to_string();
+        for player in self.players.iter() {
+            csv += &format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                player.player,
+                self.matches,
+                player.wins,
+                player.win_rate,
+                player.kills,
+                player.deaths,
+                player.suicides,
+                self.draws,
+                self.average_round_secs
+            );
+        }
+        csv
+    }
+}
+
+fn record_eliminations(
+    mut stats: ResMut<SimStats>,
+    mut ev_eliminated: EventReader<PlayerEliminated>,
+) {
+    for ev in ev_eliminated.read() {
+        stats.player(ev.player).deaths += 1;
+        match ev.killer {
+            Some(killer) if killer == ev.player => stats.player(killer).suicides += 1,
+            Some(killer) => stats.player(killer).kills += 1,
+            None => {}
+        }
+    }
+}
+
+/// Record the results of each round, and write the report after the last one.
+fn record_rounds(
+    mut stats: ResMut<SimStats>,
+    mut ev_round_over: EventReader<RoundOver>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for ev in ev_round_over.read() {
+        stats.report.matches += 1;
+        stats.total_round_secs += ev.duration.as_secs_f32();
+        match ev.winner {
+            Some(winner) => stats.player(winner).wins += 1,
+            None => stats.report.draws += 1,
+        }
+        info!("finished match {}/{}", stats.report.matches, stats.matches);
+
+        if stats.report.matches < stats.matches {
+            continue;
+        }
+
+        let stats = &mut *stats;
+        stats.report.average_round_secs = stats.total_round_secs / stats.report.matches as f32;
+        let matches = stats.report.matches as f32;
+        for player in stats.report.players.iter_mut() {
+            player.win_rate = player.wins as f32 / matches;
+        }
+
+        let report = match stats.output.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => stats.report.to_csv(),
+            _ => serde_json::to_string_pretty(&stats.report).expect("failed to serialise report"),
+        };
+        match fs::write(&stats.output, report) {
+            Ok(()) => info!("wrote simulation results to {:?}", stats.output),
+            Err(e) => error!(
+                "failed to write simulation results to {:?}: {e}",
+                stats.output
+            ),
+        }
+
+        exit.send(AppExit::Success);
+        return;
+    }
+}
@@ -0,0 +1,411 @@
+//! Snapshots of the whole gameplay state during a round, saved to and loaded from a JSON file. This
+//! makes it quick to set up a scenario when debugging, or a fixture for a test.
+//!
+//! During local play, F5 saves a snapshot and F9 loads it again. The file is `snapshot.json` in the
+//! working directory unless it is given with `--snapshot <PATH>`, which also starts the game from
+//! the snapshot. Loading a snapshot reloads its level, then restores the state on top of it.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::{TileTextureIndex, TileVisible};
+use bevy_ggrs::Session;
+use serde_derive::{Deserialize, Serialize};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::input::common_conditions::input_just_pressed;
+
+use crate::{
+    arena::{ArenaSeed, NextArenaSeed},
+    armour::Armour,
+    bomb::{spawn_bomb, Bomb, BombSprite, CountBombs},
+    door::{Door, Switch},
+    ldtk::{Destroyed, GridCoordsDef, LevelId, TileSize},
+    netplay::{NetplayConfig, NetplaySet},
+    player::{CountPlayers, Eliminated, Knockback, Player, Velocity},
+    replay::ReplayPlayer,
+    round::Round,
+    rules::Ruleset,
+    teleporter::Teleportable,
+    GameRng, GameState,
+};
+
+pub struct SnapshotPlugin;
+
+/// The version of the snapshot format. This must be increased whenever the gameplay state changes.
+pub const VERSION: u16 = 4;
+
+const DEFAULT_PATH: &str = "snapshot.json";
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SnapshotPath(DEFAULT_PATH.into()))
+            .add_systems(
+                Update,
+                (
+                    save_snapshot.run_if(input_just_pressed(KeyCode::F5)),
+                    load_snapshot.run_if(input_just_pressed(KeyCode::F9)),
+                )
+                    .chain()
+                    .after(NetplaySet::Local)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(not(resource_exists::<Session<NetplayConfig>>))
+                    .run_if(not(resource_exists::<ReplayPlayer>)),
+            )
+            .add_systems(
+                Update,
+                restore_snapshot
+                    .before(NetplaySet::Local)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<PendingSnapshot>),
+            );
+    }
+}
+
+/// Resource for the file that snapshots are saved to and loaded from.
+#[derive(Resource, Debug)]
+pub struct SnapshotPath(pub PathBuf);
+
+/// Resource for a snapshot to restore once its level has loaded.
+#[derive(Resource, Debug)]
+pub struct PendingSnapshot(pub Snapshot);
+
+/// The gameplay state during a round.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u16,
+    pub level: LevelId,
+    /// The seed of the level, if it is a generated arena, so that the same arena is loaded.
+    arena_seed: Option<u64>,
+    /// The rules the game was being played with, so that the round continues with the same rules.
+    pub ruleset: Ruleset,
+    /// The number of players spawned at the start of the round, including eliminated players.
+    pub count_players: usize,
+    /// The players still in the round.
+    players: Vec<PlayerSnapshot>,
+    bombs: Vec<BombSnapshot>,
+    bombable_tiles: Vec<TileSnapshot>,
+    doors: Vec<DoorSnapshot>,
+    switches: Vec<SwitchSnapshot>,
+    round: Round,
+    rng: GameRng,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PlayerSnapshot {
+    player: usize,
+    translation: Vec3,
+    velocity: Velocity,
+    knockback: Knockback,
+    count_bombs: CountBombs,
+    teleportable: Teleportable,
+    eliminated: Option<Eliminated>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BombSnapshot {
+    translation: Vec3,
+    bomb: Bomb,
+    teleportable: Teleportable,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TileSnapshot {
+    #[serde(with = "GridCoordsDef")]
+    coords: GridCoords,
+    destroyed: bool,
+    armour: Option<Armour>,
+    texture_index: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DoorSnapshot {
+    #[serde(with = "GridCoordsDef")]
+    coords: GridCoords,
+    door: Door,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SwitchSnapshot {
+    #[serde(with = "GridCoordsDef")]
+    coords: GridCoords,
+    pressed: bool,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let snapshot: Self = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        if snapshot.version != VERSION {
+            return Err(format!(
+                "snapshot is version {}, but this game loads version {VERSION}",
+                snapshot.version
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    /// Load the snapshot's level, and restore the snapshot once it has loaded.
+    pub fn start(self, commands: &mut Commands, next_state: &mut NextState<GameState>) {
+        commands.insert_resource(self.level.to_selection());
+        if let Some(seed) = self.arena_seed {
+            commands.insert_resource(NextArenaSeed(seed));
+        }
+        commands.insert_resource(self.ruleset.clone());
+        commands.insert_resource(CountPlayers(self.count_players));
+        commands.insert_resource(PendingSnapshot(self));
+        next_state.set(GameState::LoadingLevel);
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn save_snapshot(
+    players: Query<(
+        &Player,
+        &Transform,
+        &Velocity,
+        &Knockback,
+        &CountBombs,
+        &Teleportable,
+        Option<&Eliminated>,
+    )>,
+    bombs: Query<(&Bomb, &Transform, &Teleportable)>,
+    tiles: Query<(
+        &Parent,
+        &GridCoords,
+        &TileTextureIndex,
+        Option<&Armour>,
+        Has<Destroyed>,
+    )>,
+    doors: Query<(&Door, &GridCoords)>,
+    switches: Query<(&Switch, &GridCoords)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    level_selection: Res<LevelSelection>,
+    arena_seed: Option<Res<ArenaSeed>>,
+    ruleset: Res<Ruleset>,
+    count_players: Res<CountPlayers>,
+    round: Res<Round>,
+    rng: Res<GameRng>,
+    path: Res<SnapshotPath>,
+) {
+    let mut players = players
+        .iter()
+        .map(
+            |(player, transform, velocity, knockback, count_bombs, teleportable, eliminated)| {
+                PlayerSnapshot {
+                    player: player.0,
+                    translation: transform.translation,
+                    velocity: velocity.clone(),
+                    knockback: knockback.clone(),
+                    count_bombs: count_bombs.clone(),
+                    teleportable: teleportable.clone(),
+                    eliminated: eliminated.cloned(),
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    players.sort_by_key(|player| player.player);
+
+    let bombs = bombs
+        .iter()
+        .map(|(bomb, transform, teleportable)| BombSnapshot {
+            translation: transform.translation,
+            bomb: bomb.clone(),
+            teleportable: teleportable.clone(),
+        })
+        .collect();
+
+    let bombable_tiles = tiles
+        .iter()
+        .filter(|(parent, ..)| {
+            ldtk_layer_meta_q
+                .get(***parent)
+                .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+        })
+        .map(
+            |(_, coords, texture_index, armour, destroyed)| TileSnapshot {
+                coords: *coords,
+                destroyed,
+                armour: armour.cloned(),
+                texture_index: texture_index.0,
+            },
+        )
+        .collect();
+
+    let doors = doors
+        .iter()
+        .map(|(door, coords)| DoorSnapshot {
+            coords: *coords,
+            door: door.clone(),
+        })
+        .collect();
+
+    let switches = switches
+        .iter()
+        .map(|(switch, coords)| SwitchSnapshot {
+            coords: *coords,
+            pressed: switch.pressed,
+        })
+        .collect();
+
+    let snapshot = Snapshot {
+        version: VERSION,
+        level: LevelId::from_selection(&level_selection),
+        arena_seed: arena_seed.map(|seed| seed.0),
+        ruleset: ruleset.clone(),
+        count_players: count_players.0,
+        players,
+        bombs,
+        bombable_tiles,
+        doors,
+        switches,
+        round: round.clone(),
+        rng: rng.clone(),
+    };
+
+    let result = serde_json::to_string_pretty(&snapshot)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&path.0, json).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => info!("saved snapshot to {:?}", path.0),
+        Err(e) => warn!("failed to save snapshot to {:?}: {e}", path.0),
+    }
+}
+
+/// Load the snapshot at the [`SnapshotPath`]. If the game was started from the snapshot and it
+/// can't be loaded, this goes to the main menu instead.
+pub fn load_snapshot(
+    mut commands: Commands,
+    path: Res<SnapshotPath>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    match Snapshot::load(&path.0) {
+        Ok(snapshot) => {
+            info!("loading snapshot from {:?}", path.0);
+            snapshot.start(&mut commands, &mut next_state);
+        }
+        Err(e) => {
+            warn!("failed to load snapshot from {:?}: {e}", path.0);
+            if *state.get() != GameState::InGame {
+                next_state.set(GameState::MainMenu);
+            }
+        }
+    }
+}
+
+/// Restore the pending snapshot on top of the freshly loaded level.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn restore_snapshot(
+    mut commands: Commands,
+    mut players: Query<(
+        Entity,
+        &Player,
+        &mut Transform,
+        &mut Velocity,
+        &mut Knockback,
+        &mut CountBombs,
+        &mut Teleportable,
+    )>,
+    mut tiles: Query<(Entity, &Parent, &GridCoords, &mut TileTextureIndex)>,
+    mut doors: Query<(&mut Door, &GridCoords)>,
+    mut switches: Query<(&mut Switch, &GridCoords)>,
+    mut round: ResMut<Round>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    mut rng: ResMut<GameRng>,
+    bomb_sprite: Res<BombSprite>,
+    pending: Res<PendingSnapshot>,
+    tile_size: Res<TileSize>,
+) {
+    let snapshot = &pending.0;
+
+    for (
+        entity,
+        player,
+        mut transform,
+        mut velocity,
+        mut knockback,
+        mut count_bombs,
+        mut teleportable,
+    ) in players.iter_mut()
+    {
+        let Some(saved) = snapshot
+            .players
+            .iter()
+            .find(|saved| saved.player == player.0)
+        else {
+            // The player had already been eliminated.
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        transform.translation = saved.translation;
+        *velocity = saved.velocity.clone();
+        *knockback = saved.knockback.clone();
+        *count_bombs = saved.count_bombs.clone();
+        *teleportable = saved.teleportable.clone();
+        if let Some(eliminated) = &saved.eliminated {
+            commands.entity(entity).insert(eliminated.clone());
+        }
+    }
+
+    for saved in snapshot.bombs.iter() {
+        spawn_bomb(
+            &mut commands,
+            &bomb_sprite,
+            saved.translation,
+            saved.bomb.clone(),
+            saved.teleportable.clone(),
+            *tile_size,
+        );
+    }
+
+    for (entity, _, coords, mut texture_index) in tiles.iter_mut().filter(|(_, parent, ..)| {
+        ldtk_layer_meta_q
+            .get(***parent)
+            .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+    }) {
+        let Some(saved) = snapshot
+            .bombable_tiles
+            .iter()
+            .find(|saved| saved.coords == *coords)
+        else {
+            continue;
+        };
+
+        texture_index.0 = saved.texture_index;
+        let mut tile = commands.entity(entity);
+        match &saved.armour {
+            Some(armour) => tile.insert(armour.clone()),
+            None => tile.remove::<Armour>(),
+        };
+        if saved.destroyed {
+            tile.insert((Destroyed, TileVisible(false)));
+        }
+    }
+
+    for (mut door, coords) in doors.iter_mut() {
+        if let Some(saved) = snapshot.doors.iter().find(|saved| saved.coords == *coords) {
+            *door = saved.door.clone();
+        }
+    }
+
+    for (mut switch, coords) in switches.iter_mut() {
+        if let Some(saved) = snapshot
+            .switches
+            .iter()
+            .find(|saved| saved.coords == *coords)
+        {
+            switch.pressed = saved.pressed;
+        }
+    }
+
+    *round = snapshot.round.clone();
+    *rng = snapshot.rng.clone();
+
+    info!("restored snapshot");
+    commands.remove_resource::<PendingSnapshot>();
+}
@@ -0,0 +1,214 @@
+//! Sync test mode for netplay, started with `--sync-test <N>`. This plays a local session which
+//! rolls back N frames on every frame and re-simulates them, so any non-determinism in the
+//! [`Gameplay`](crate::netplay::Gameplay) schedule, or state that isn't registered for rollback,
+//! shows up straight away instead of as a rare desync online.
+//!
+//! The gameplay state is recorded after each frame is simulated, and compared each time the frame
+//! is re-simulated. The first difference is logged along with the component it was found in.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::{prelude::*, RollbackFrameCount};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use std::{collections::HashMap, fmt::Debug};
+
+use crate::{
+    armour::Armour,
+    bomb::{Bomb, CountBombs},
+    door::Door,
+    ldtk::{Destroyed, TileSize, ToGrid},
+    netplay::{in_sync_test, NetplayConfig, NetplayMode, NetplaySet, NetplaySettings, INPUT_MASK},
+    player::{Eliminated, Knockback, Player, Velocity},
+    round::Round,
+    GameRng, GameState,
+};
+
+pub struct SyncTestPlugin;
+
+/// The chance on each frame that a randomly controlled player changes their input.
+const INPUT_CHANGE_CHANCE: f64 = 0.1;
+
+impl Plugin for SyncTestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StateHistory>()
+            .insert_resource(TestInputs {
+                rng: SmallRng::seed_from_u64(0),
+                inputs: HashMap::new(),
+            })
+            .add_systems(
+                ReadInputs,
+                randomise_inputs
+                    .after(NetplaySet::ReadInputs)
+                    .run_if(in_sync_test),
+            )
+            .add_systems(
+                GgrsSchedule,
+                check_state.after(NetplaySet::Simulate).run_if(in_sync_test),
+            )
+            .add_systems(OnExit(GameState::InGame), clear_history);
+    }
+}
+
+/// The gameplay state as a list of labelled values, always in the same order.
+type GameplayState = Vec<(String, String)>;
+
+/// The gameplay state after the first simulation of each frame that can still be re-simulated.
+#[derive(Resource, Default)]
+struct StateHistory(HashMap<i32, GameplayState>);
+
+/// Random inputs for the players who aren't controlled by the keyboard, so that the test covers
+/// more than one player at a time.
+#[derive(Resource)]
+struct TestInputs {
+    rng: SmallRng,
+    /// The input currently held by each player.
+    inputs: HashMap<usize, u8>,
+}
+
+/// Replace the input of every player except the first with a random input, which is held for a
+/// few frames at a time.
+fn randomise_inputs(
+    mut local_inputs: ResMut<LocalInputs<NetplayConfig>>,
+    mut test_inputs: ResMut<TestInputs>,
+) {
+    let TestInputs { rng, inputs } = &mut *test_inputs;
+
+    let mut handles = local_inputs
+        .0
+        .keys()
+        .copied()
+        .filter(|handle| *handle != 0)
+        .collect::<Vec<_>>();
+    handles.sort_unstable();
+
+    for handle in handles {
+        let held = inputs.entry(handle).or_default();
+        if rng.gen_bool(INPUT_CHANGE_CHANCE) {
+            *held = rng.gen::<u8>() & INPUT_MASK;
+        }
+        local_inputs.0.insert(handle, *held);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_state(
+    frame: Res<RollbackFrameCount>,
+    settings: Res<NetplaySettings>,
+    mut history: ResMut<StateHistory>,
+    players: Query<(
+        &Player,
+        &Transform,
+        &Velocity,
+        &Knockback,
+        &CountBombs,
+        Option<&Eliminated>,
+    )>,
+    bombs: Query<(&Bomb, &Transform)>,
+    tiles: Query<(&Parent, &GridCoords, Option<&Armour>), Without<Destroyed>>,
+    doors: Query<(&Door, &GridCoords)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    round: Res<Round>,
+    rng: Res<GameRng>,
+    tile_size: Res<TileSize>,
+) {
+    let mut state = GameplayState::new();
+    let mut record = |label: String, value: &dyn Debug| state.push((label, format!("{value:?}")));
+
+    let mut players = players.iter().collect::<Vec<_>>();
+    players.sort_by_key(|(player, ..)| player.0);
+    for (player, transform, velocity, knockback, count_bombs, eliminated) in players {
+        let name = format!("Player {}", player.0 + 1);
+        // The Z value is only used for sorting sprites, so it isn't part of the gameplay state.
+        record(
+            format!("{name} position"),
+            &transform.translation.truncate(),
+        );
+        record(format!("{name} Velocity"), velocity);
+        record(format!("{name} Knockback"), knockback);
+        record(format!("{name} CountBombs"), count_bombs);
+        record(format!("{name} Eliminated"), &eliminated);
+    }
+
+    let mut bombs = bombs
+        .iter()
+        .map(|(bomb, transform)| (transform.translation.to_grid(*tile_size), bomb))
+        .collect::<Vec<_>>();
+    bombs.sort_by_key(|(coords, _)| (coords.x, coords.y));
+    for (coords, bomb) in bombs {
+        record(format!("Bomb at {coords:?}"), bomb);
+    }
+
+    let mut tiles = tiles
+        .iter()
+        .filter(|(parent, _, _)| {
+            ldtk_layer_meta_q
+                .get(***parent)
+                .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Bombable")
+        })
+        .map(|(_, coords, armour)| (*coords, armour))
+        .collect::<Vec<_>>();
+    tiles.sort_by_key(|(coords, _)| (coords.x, coords.y));
+    for (coords, armour) in tiles {
+        record(format!("Bombable tile at {coords:?}"), &armour);
+    }
+
+    let mut doors = doors.iter().collect::<Vec<_>>();
+    doors.sort_by_key(|(_, coords)| (coords.x, coords.y));
+    for (door, coords) in doors {
+        record(format!("Door at {coords:?}"), door);
+    }
+
+    record("Round".to_string(), &*round);
+    record("GameRng".to_string(), &rng.0);
+
+    match history.0.get(&frame.0) {
+        Some(first) => {
+            if let Some(difference) = find_difference(first, &state) {
+                error!("sync test failed on frame {}: {difference}", frame.0);
+            }
+        }
+        None => {
+            history.0.insert(frame.0, state);
+        }
+    }
+
+    // Frames are re-simulated until they are `check_distance` frames old, so every frame since then
+    // needs to be kept however far back that is.
+    let check_distance = match settings.mode {
+        NetplayMode::SyncTest { check_distance } => check_distance as i32,
+        NetplayMode::P2P { .. } => 0,
+    };
+    history.0.retain(|f, _| *f >= frame.0 - check_distance);
+}
+
+/// Forget the state of the last round, as each round has its own session, whose frames start from
+/// 0 again.
+fn clear_history(mut history: ResMut<StateHistory>) {
+    history.0.clear();
+}
+
+/// Describe the first difference between the state from the first simulation of a frame and the
+/// state after re-simulating it, if there is one.
+fn find_difference(first: &GameplayState, resimulated: &GameplayState) -> Option<String> {
+    first
+        .iter()
+        .zip(resimulated.iter())
+        .find(|(a, b)| a != b)
+        .map(|((label_a, value_a), (label_b, value_b))| {
+            if label_a == label_b {
+                format!("{label_a} was {value_a}, but is {value_b} after a rollback")
+            } else {
+                format!("expected {label_a}, but found {label_b} after a rollback")
+            }
+        })
+        .or_else(|| {
+            (first.len() != resimulated.len()).then(|| {
+                format!(
+                    "expected {} values, but found {} after a rollback",
+                    first.len(),
+                    resimulated.len()
+                )
+            })
+        })
+}
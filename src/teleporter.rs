@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    ldtk::{GridCoordsDef, GridNormalise, TileSize, ToGrid, ToWorld},
+    netplay::Gameplay,
+    GameState,
+};
+
+pub struct TeleporterPlugin;
+
+/// How long an entity must wait after teleporting before it can teleport again.
+const TELEPORT_COOLDOWN_SECS: f32 = 0.5;
+
+impl Plugin for TeleporterPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity::<TeleporterBundle>("Teleporter")
+            .add_systems(Gameplay, teleport.run_if(in_state(GameState::InGame)));
+    }
+}
+
+/// A teleporter tile placed in LDtk. The partner is set with the `Partner` entity reference field,
+/// and a teleporter without one acts as an exit only.
+#[derive(Component, Default, Debug)]
+pub struct Teleporter {
+    partner: Option<EntityIid>,
+}
+
+impl From<&EntityInstance> for Teleporter {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        let partner = entity_instance
+            .get_maybe_entity_ref_field("Partner")
+            .unwrap_or_else(|e| {
+                warn!(
+                    "invalid partner for teleporter {}: {e}",
+                    entity_instance.iid
+                );
+                &None
+            })
+            .as_ref()
+            .map(|entity_ref| EntityIid::new(entity_ref.entity_iid.clone()));
+
+        Self { partner }
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+struct TeleporterBundle {
+    #[from_entity_instance]
+    teleporter: Teleporter,
+    #[grid_coords]
+    grid_coords: GridCoords,
+}
+
+/// Component for entities that can be moved by a [`Teleporter`]. Entities only teleport on
+/// entering a teleporter tile, so something placed on a teleporter (such as a bomb) stays put.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Teleportable {
+    #[serde(with = "GridCoordsDef")]
+    prev_coords: GridCoords,
+    cooldown: Timer,
+}
+
+impl Teleportable {
+    pub fn new(coords: GridCoords) -> Self {
+        let mut cooldown = Timer::from_seconds(TELEPORT_COOLDOWN_SECS, TimerMode::Once);
+        cooldown.tick(cooldown.duration());
+        Self {
+            prev_coords: coords,
+            cooldown,
+        }
+    }
+}
+
+/// Move entities entering a [`Teleporter`] tile to its partner, keeping their offset within the
+/// tile.
+fn teleport(
+    mut teleportables: Query<(&mut Transform, &mut Teleportable)>,
+    teleporters: Query<(&Teleporter, &GridCoords)>,
+    partners: Query<(&EntityIid, &GridCoords), With<Teleporter>>,
+    time: Res<Time>,
+    tile_size: Res<TileSize>,
+) {
+    for (mut transform, mut teleportable) in teleportables.iter_mut() {
+        teleportable.cooldown.tick(time.delta());
+
+        let coords = transform.translation.to_grid(*tile_size);
+        if coords == teleportable.prev_coords {
+            continue;
+        }
+        teleportable.prev_coords = coords;
+
+        if !teleportable.cooldown.finished() {
+            continue;
+        }
+
+        let Some(destination) = teleporters
+            .iter()
+            .find(|(_, teleporter_coords)| **teleporter_coords == coords)
+            .and_then(|(teleporter, _)| teleporter.partner.as_ref())
+            .and_then(|partner| {
+                partners
+                    .iter()
+                    .find(|(iid, _)| *iid == partner)
+                    .map(|(_, partner_coords)| *partner_coords)
+            })
+        else {
+            continue;
+        };
+
+        let offset =
+            transform.translation.truncate() - transform.translation.grid_normalised(*tile_size);
+        transform.translation =
+            (destination.to_world(*tile_size) + offset).extend(transform.translation.z);
+
+        teleportable.prev_coords = destination;
+        teleportable.cooldown.reset();
+    }
+}
@@ -0,0 +1,162 @@
+//! Tides for water levels. The tiles of the `Tide` layer of an LDtk level are flooded when the tide
+//! comes in, which happens every `tide_period_secs` and lasts for `tide_flood_secs`, as set by the
+//! [`LevelRules`]. Bombs on a flooded tile are put out without exploding, and players wade through
+//! flooded tiles at [`WADING_SPEED`]. Levels without a `Tide` layer have no tide.
+//!
+//! The `Tide` tiles are hidden while the tide is out, and fade in a few seconds before the tide
+//! comes in as a warning. The tide is timed from [`Round::elapsed`], and the flooded tiles are
+//! worked out from it whenever they are needed rather than stored, so that the tide is rolled back
+//! with the round during netplay.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::tiles::{TileColor, TileVisible};
+
+use crate::{
+    bomb::{Bomb, BombSet, CountBombs},
+    ldtk::{TileSize, ToGrid},
+    netplay::Gameplay,
+    player::Player,
+    round::Round,
+    rules::LevelRules,
+    GameState,
+};
+
+pub struct TidePlugin;
+
+/// How long before the tide comes in that the flooded tiles start to show.
+const WARNING_SECS: f32 = 3.0;
+
+/// The speed of players wading through a flooded tile, as a fraction of their normal speed.
+pub const WADING_SPEED: f32 = 0.5;
+
+/// The opacity of the `Tide` tiles while the tide is in.
+const FLOODED_ALPHA: f32 = 0.8;
+
+impl Plugin for TidePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Gameplay,
+            extinguish_bombs
+                .before(BombSet)
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(Update, show_tide.run_if(in_state(GameState::InGame)));
+    }
+}
+
+/// Whether the tide is in at some point in the round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TideState {
+    Out,
+    /// The tide is out, but comes in after this many seconds.
+    Warning(f32),
+    In,
+}
+
+impl TideState {
+    /// The state of the tide after `elapsed_secs` of the round. The first tide comes in once a
+    /// whole period has passed, so that players have time to get out of the water.
+    pub fn at(elapsed_secs: f32, rules: &LevelRules) -> Self {
+        let period = rules.0.tide_period_secs;
+        if period <= 0.0 {
+            return Self::Out;
+        }
+        let flood = rules.0.tide_flood_secs.min(period);
+
+        let into_period = elapsed_secs % period;
+        if elapsed_secs >= period && into_period < flood {
+            Self::In
+        } else if period - into_period <= WARNING_SECS {
+            Self::Warning(period - into_period)
+        } else {
+            Self::Out
+        }
+    }
+
+    fn is_in(self) -> bool {
+        self == Self::In
+    }
+}
+
+/// The coordinates of every tile on the `Tide` layer that is flooded at this point in the round,
+/// which is none of them while the tide is out.
+pub fn flooded_tiles(
+    tiles: &Query<(&Parent, &GridCoords)>,
+    ldtk_layer_meta_q: &Query<&LayerMetadata>,
+    round: &Round,
+    rules: &LevelRules,
+) -> Vec<GridCoords> {
+    if !TideState::at(round.elapsed.as_secs_f32(), rules).is_in() {
+        return Vec::new();
+    }
+
+    tiles
+        .iter()
+        .filter(|(parent, _)| {
+            ldtk_layer_meta_q
+                .get(***parent)
+                .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Tide")
+        })
+        .map(|(_, coords)| *coords)
+        .collect()
+}
+
+/// Put out bombs on flooded tiles, giving them back to the players who placed them.
+#[allow(clippy::too_many_arguments)]
+fn extinguish_bombs(
+    mut commands: Commands,
+    bombs: Query<(Entity, &Bomb, &Transform)>,
+    mut players: Query<(&Player, &mut CountBombs)>,
+    tiles: Query<(&Parent, &GridCoords)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    round: Res<Round>,
+    rules: Res<LevelRules>,
+    tile_size: Res<TileSize>,
+) {
+    if bombs.is_empty() {
+        return;
+    }
+
+    let flooded = flooded_tiles(&tiles, &ldtk_layer_meta_q, &round, &rules);
+    for (entity, bomb, transform) in bombs.iter() {
+        if !flooded.contains(&transform.translation.to_grid(*tile_size)) {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+        if let Some((_, mut count_bombs)) = players
+            .iter_mut()
+            .find(|(player, _)| player.0 == bomb.owner())
+        {
+            count_bombs.release();
+        }
+    }
+}
+
+/// Show the `Tide` tiles while the tide is in, and flash them while it is about to come in.
+fn show_tide(
+    mut tiles: Query<(&Parent, &mut TileVisible, &mut TileColor)>,
+    ldtk_layer_meta_q: Query<&LayerMetadata>,
+    round: Res<Round>,
+    rules: Res<LevelRules>,
+) {
+    let alpha = match TideState::at(round.elapsed.as_secs_f32(), &rules) {
+        TideState::Out => 0.0,
+        TideState::Warning(secs) => {
+            // Pulse once a second, getting stronger as the tide gets closer.
+            let pulse = (secs * std::f32::consts::TAU).cos() * 0.5 + 0.5;
+            (1.0 - secs / WARNING_SECS) * pulse * FLOODED_ALPHA
+        }
+        TideState::In => FLOODED_ALPHA,
+    };
+
+    for (_, mut visible, mut color) in tiles.iter_mut().filter(|(parent, ..)| {
+        ldtk_layer_meta_q
+            .get(***parent)
+            .is_ok_and(|ldtk_layer| ldtk_layer.identifier == "Tide")
+    }) {
+        visible.0 = alpha > 0.0;
+        color.0.set_alpha(alpha);
+    }
+}
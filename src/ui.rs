@@ -1,8 +1,22 @@
 use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
 
 use bevy::{app::AppExit, ui::widget::NodeImageMode};
 
-use crate::GameState;
+use std::net::SocketAddr;
+
+use crate::{
+    arena::GENERATED_LEVEL,
+    bot::BotDifficulty,
+    config::Config,
+    ldtk::{level_summaries, LdtkProjectAsset, LevelId, LevelSummary},
+    lobby::{Lobby, LobbyRequest, LobbyRole, LobbyState},
+    netplay::MAX_PLAYERS,
+    player::{CountPlayers, PlayerController},
+    round::Round,
+    rules::Ruleset,
+    GameState,
+};
 
 pub struct UiPlugin;
 
@@ -12,22 +26,57 @@ impl Plugin for UiPlugin {
             .add_systems(OnEnter(GameState::MainMenu), setup)
             .add_systems(
                 Update,
-                detect_button_presses.run_if(in_state(GameState::MainMenu)),
+                (
+                    detect_button_presses,
+                    update_player_slot_labels.run_if(resource_changed::<Config>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::MainMenu)),
+            )
+            .add_systems(OnExit(GameState::MainMenu), despawn_ui)
+            .add_systems(OnEnter(GameState::LevelSelect), setup_level_select)
+            .add_systems(
+                Update,
+                (detect_level_button_presses, update_level_list)
+                    .chain()
+                    .run_if(in_state(GameState::LevelSelect)),
+            )
+            .add_systems(OnExit(GameState::LevelSelect), despawn_ui)
+            .add_systems(OnEnter(GameState::Lobby), setup_lobby)
+            .add_systems(
+                Update,
+                (
+                    detect_lobby_button_presses,
+                    update_lobby_screen.run_if(resource_exists::<Lobby>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(OnExit(GameState::Lobby), despawn_ui)
+            .add_systems(
+                Update,
+                show_next_level.run_if(in_state(GameState::InGame).and(resource_changed::<Round>)),
             )
-            .add_systems(OnExit(GameState::MainMenu), despawn_ui);
+            .add_systems(OnExit(GameState::InGame), despawn_ui);
     }
 }
 
 #[derive(Component)]
 enum MainMenuButton {
     Start,
+    /// Cycles who controls the player slot with this index.
+    PlayerSlot(usize),
+    HostLan,
+    JoinLan,
     Exit,
 }
 
 fn detect_button_presses(
-    buttons: Query<(&MainMenuButton, &Interaction)>,
+    mut commands: Commands,
+    buttons: Query<(&MainMenuButton, &Interaction), Changed<Interaction>>,
     mut next_state: ResMut<NextState<GameState>>,
     mut exit: EventWriter<AppExit>,
+    mut config: ResMut<Config>,
 ) {
     for button in buttons
         .iter()
@@ -35,7 +84,16 @@ fn detect_button_presses(
         .map(|b| b.0)
     {
         match button {
-            MainMenuButton::Start => next_state.set(GameState::LoadingLevel),
+            MainMenuButton::Start => next_state.set(GameState::LevelSelect),
+            MainMenuButton::PlayerSlot(slot) => cycle_player_slot(&mut config, *slot),
+            MainMenuButton::HostLan => {
+                commands.insert_resource(LobbyRole::Host);
+                next_state.set(GameState::Lobby);
+            }
+            MainMenuButton::JoinLan => {
+                commands.insert_resource(LobbyRole::Client);
+                next_state.set(GameState::Lobby);
+            }
             MainMenuButton::Exit => {
                 exit.send(AppExit::Success);
             }
@@ -43,6 +101,63 @@ fn detect_button_presses(
     }
 }
 
+/// Cycle the player slot through human control and each bot difficulty.
+fn cycle_player_slot(config: &mut Config, slot: usize) {
+    if config.players.len() <= slot {
+        config.players.resize(slot + 1, PlayerController::default());
+    }
+    if config.bot_difficulty.len() <= slot {
+        config
+            .bot_difficulty
+            .resize(slot + 1, BotDifficulty::default());
+    }
+
+    match config.players[slot] {
+        PlayerController::Human => {
+            config.players[slot] = PlayerController::Bot;
+            config.bot_difficulty[slot] = BotDifficulty::Easy;
+        }
+        PlayerController::Bot => match config.bot_difficulty[slot] {
+            BotDifficulty::Easy => config.bot_difficulty[slot] = BotDifficulty::Normal,
+            BotDifficulty::Normal => config.bot_difficulty[slot] = BotDifficulty::Hard,
+            BotDifficulty::Hard => config.players[slot] = PlayerController::Human,
+        },
+        PlayerController::Network => config.players[slot] = PlayerController::Human,
+    }
+}
+
+fn player_slot_label(config: &Config, slot: usize) -> String {
+    match config.players.get(slot).copied().unwrap_or_default() {
+        PlayerController::Human => format!("Player {}: Human", slot + 1),
+        PlayerController::Bot => format!(
+            "Player {}: Bot ({:?})",
+            slot + 1,
+            config.bot_difficulty.get(slot).copied().unwrap_or_default()
+        ),
+        PlayerController::Network => format!("Player {}: Online", slot + 1),
+    }
+}
+
+fn update_player_slot_labels(
+    buttons: Query<(&MainMenuButton, &Children)>,
+    mut texts: Query<&mut Text>,
+    config: Res<Config>,
+) {
+    for (slot, children) in buttons
+        .iter()
+        .filter_map(|(button, children)| match button {
+            MainMenuButton::PlayerSlot(slot) => Some((*slot, children)),
+            _ => None,
+        })
+    {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.0 = player_slot_label(&config, slot);
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 struct DespawnOnExit;
 
@@ -52,7 +167,13 @@ fn despawn_ui(mut commands: Commands, to_despawn: Query<Entity, With<DespawnOnEx
         .for_each(|e| commands.entity(e).despawn_recursive());
 }
 
-fn setup(mut commands: Commands, font: Res<FontHandle>, button: Res<ButtonNinePatch>) {
+fn setup(
+    mut commands: Commands,
+    font: Res<FontHandle>,
+    button: Res<ButtonNinePatch>,
+    config: Res<Config>,
+    count_players: Res<CountPlayers>,
+) {
     let start_button = spawn_green_button_with_text(&mut commands, &font, &button, "Start Game");
     let start_button = commands
         .entity(start_button)
@@ -60,6 +181,34 @@ fn setup(mut commands: Commands, font: Res<FontHandle>, button: Res<ButtonNinePa
         .insert(Name::new("Start button"))
         .id();
 
+    let player_slot_buttons = (0..count_players.0)
+        .map(|slot| {
+            let label = player_slot_label(&config, slot);
+            let slot_button = spawn_green_button_with_text(&mut commands, &font, &button, &label);
+            commands
+                .entity(slot_button)
+                .insert(MainMenuButton::PlayerSlot(slot))
+                .insert(Name::new(format!("Player {} slot button", slot + 1)))
+                .id()
+        })
+        .collect::<Vec<_>>();
+
+    let host_lan_button =
+        spawn_green_button_with_text(&mut commands, &font, &button, "Host LAN Game");
+    let host_lan_button = commands
+        .entity(host_lan_button)
+        .insert(MainMenuButton::HostLan)
+        .insert(Name::new("Host LAN button"))
+        .id();
+
+    let join_lan_button =
+        spawn_green_button_with_text(&mut commands, &font, &button, "Join LAN Game");
+    let join_lan_button = commands
+        .entity(join_lan_button)
+        .insert(MainMenuButton::JoinLan)
+        .insert(Name::new("Join LAN button"))
+        .id();
+
     let exit_button = spawn_green_button_with_text(&mut commands, &font, &button, "Exit");
     let exit_button = commands
         .entity(exit_button)
@@ -81,9 +230,389 @@ fn setup(mut commands: Commands, font: Res<FontHandle>, button: Res<ButtonNinePa
             DespawnOnExit,
         ))
         .add_child(start_button)
+        .add_children(&player_slot_buttons)
+        .add_child(host_lan_button)
+        .add_child(join_lan_button)
         .add_child(exit_button);
 }
 
+#[derive(Component)]
+enum LevelButton {
+    /// Play the level with this identifier.
+    Play(String),
+    Back,
+}
+
+/// Component for the node containing a button for each level in the LDtk project, with the levels
+/// it has buttons for.
+#[derive(Component, Default)]
+struct LevelList(Vec<LevelSummary>);
+
+fn setup_level_select(mut commands: Commands, font: Res<FontHandle>, button: Res<ButtonNinePatch>) {
+    let title = commands
+        .spawn((
+            Text::new("Choose a level"),
+            TextFont {
+                font: font.0.clone(),
+                font_size: 30.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new_with_justify(JustifyText::Center),
+        ))
+        .id();
+
+    let level_list = commands
+        .spawn((
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            LevelList::default(),
+        ))
+        .id();
+
+    let back_button = spawn_green_button_with_text(&mut commands, &font, &button, "Back");
+    let back_button = commands
+        .entity(back_button)
+        .insert(LevelButton::Back)
+        .insert(Name::new("Back button"))
+        .id();
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                padding: UiRect::top(Val::Percent(25.0)),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexStart,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            DespawnOnExit,
+        ))
+        .add_child(title)
+        .add_child(level_list)
+        .add_child(back_button);
+}
+
+fn detect_level_button_presses(
+    mut commands: Commands,
+    buttons: Query<(&LevelButton, &Interaction), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for button in buttons
+        .iter()
+        .filter(|(_, state)| **state == Interaction::Pressed)
+        .map(|b| b.0)
+    {
+        match button {
+            LevelButton::Play(identifier) => {
+                commands.insert_resource(LevelSelection::Identifier(identifier.clone()));
+                next_state.set(GameState::LoadingLevel);
+            }
+            LevelButton::Back => next_state.set(GameState::MainMenu),
+        }
+    }
+}
+
+/// List the levels in the LDtk project once it has loaded, followed by a generated arena.
+#[allow(clippy::too_many_arguments)]
+fn update_level_list(
+    mut commands: Commands,
+    project: Res<LdtkProjectAsset>,
+    ldtk_assets: Res<Assets<LdtkProject>>,
+    ruleset: Res<Ruleset>,
+    mut level_list: Single<(Entity, &mut LevelList)>,
+    font: Res<FontHandle>,
+    button: Res<ButtonNinePatch>,
+) {
+    let Some(project) = ldtk_assets.get(&project.0) else {
+        return;
+    };
+    let (width, height) = ruleset.arena.size();
+    let mut levels = level_summaries(project);
+    levels.push(LevelSummary {
+        identifier: GENERATED_LEVEL.to_string(),
+        width,
+        height,
+    });
+
+    let (level_list, listed_levels) = &mut *level_list;
+    if listed_levels.0 == levels {
+        return;
+    }
+
+    commands.entity(*level_list).despawn_descendants();
+    for level in levels.iter() {
+        let label = format!(
+            "{} ({}x{})",
+            level_name(&level.identifier),
+            level.width,
+            level.height
+        );
+        let level_button = spawn_green_button_with_text(&mut commands, &font, &button, &label);
+        commands
+            .entity(level_button)
+            .insert(LevelButton::Play(level.identifier.clone()))
+            .insert(Name::new(format!("{} level button", level.identifier)))
+            .set_parent(*level_list);
+    }
+    listed_levels.0 = levels;
+}
+
+/// The name to show for a level in the UI.
+fn level_name(identifier: &str) -> &str {
+    match identifier {
+        GENERATED_LEVEL => "Random Arena",
+        identifier => identifier,
+    }
+}
+
+/// The key rules of a game, to show before joining it.
+fn rules_summary(ruleset: &Ruleset) -> String {
+    let round_time = match ruleset.round_time_secs {
+        Some(secs) => format!("{secs}s rounds"),
+        None => "no time limit".to_string(),
+    };
+    format!(
+        "{}s fuse, {} bombs, range {}, {round_time}",
+        ruleset.bomb_timer_secs, ruleset.max_bombs, ruleset.blast_range
+    )
+}
+
+/// The name to show for a level chosen by another player, which may not have an identifier.
+fn level_id_name(level: &LevelId) -> String {
+    match level {
+        LevelId::Identifier(identifier) => level_name(identifier).to_string(),
+        LevelId::Index(index) => format!("Level {}", index + 1),
+    }
+}
+
+/// Marker component for the text showing the level chosen for the next round.
+#[derive(Component)]
+struct NextLevelText;
+
+/// Show the level chosen from the playlist for the next round, while waiting for it to load. The
+/// text is removed again if the end of the round is rolled back during netplay.
+fn show_next_level(
+    mut commands: Commands,
+    round: Res<Round>,
+    text: Query<Entity, With<NextLevelText>>,
+    font: Res<FontHandle>,
+) {
+    match (&round.next_level, text.get_single()) {
+        (Some(level), Err(_)) => {
+            commands
+                .spawn((
+                    Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    NextLevelText,
+                    DespawnOnExit,
+                ))
+                .with_child((
+                    Text::new(format!("Next level: {}", level_name(level))),
+                    TextFont {
+                        font: font.0.clone(),
+                        font_size: 30.0,
+                        ..default()
+                    },
+                    TextColor(Color::WHITE),
+                    TextLayout::new_with_justify(JustifyText::Center),
+                ));
+        }
+        (None, Ok(text)) => commands.entity(text).despawn_recursive(),
+        _ => {}
+    }
+}
+
+#[derive(Component)]
+enum LobbyButton {
+    /// Start the game, as the host.
+    Start,
+    /// Join the game hosted at this address.
+    Join(SocketAddr),
+    Back,
+}
+
+/// Marker component for the text describing the state of the lobby.
+#[derive(Component)]
+struct LobbyStatus;
+
+/// Component for the node containing a button for each game found in the lobby, with the games it
+/// has buttons for.
+#[derive(Component, Default)]
+struct LobbyGameList(Vec<(SocketAddr, String)>);
+
+fn setup_lobby(
+    mut commands: Commands,
+    font: Res<FontHandle>,
+    button: Res<ButtonNinePatch>,
+    role: Res<LobbyRole>,
+) {
+    let status = commands
+        .spawn((
+            Text::new(""),
+            TextFont {
+                font: font.0.clone(),
+                font_size: 30.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new_with_justify(JustifyText::Center),
+            LobbyStatus,
+        ))
+        .id();
+
+    let game_list = commands
+        .spawn((
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            LobbyGameList::default(),
+        ))
+        .id();
+
+    let mut buttons = Vec::new();
+    if *role == LobbyRole::Host {
+        let start_button =
+            spawn_green_button_with_text(&mut commands, &font, &button, "Start Game");
+        buttons.push(
+            commands
+                .entity(start_button)
+                .insert(LobbyButton::Start)
+                .insert(Name::new("Start LAN game button"))
+                .id(),
+        );
+    }
+
+    let back_button = spawn_green_button_with_text(&mut commands, &font, &button, "Back");
+    buttons.push(
+        commands
+            .entity(back_button)
+            .insert(LobbyButton::Back)
+            .insert(Name::new("Back button"))
+            .id(),
+    );
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                padding: UiRect::top(Val::Percent(25.0)),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexStart,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            DespawnOnExit,
+        ))
+        .add_child(status)
+        .add_child(game_list)
+        .add_children(&buttons);
+}
+
+fn detect_lobby_button_presses(
+    buttons: Query<(&LobbyButton, &Interaction), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut ev_request: EventWriter<LobbyRequest>,
+) {
+    for button in buttons
+        .iter()
+        .filter(|(_, state)| **state == Interaction::Pressed)
+        .map(|b| b.0)
+    {
+        match button {
+            LobbyButton::Start => {
+                ev_request.send(LobbyRequest::Start);
+            }
+            LobbyButton::Join(host) => {
+                ev_request.send(LobbyRequest::Join(*host));
+            }
+            LobbyButton::Back => next_state.set(GameState::MainMenu),
+        }
+    }
+}
+
+/// Keep the lobby status up to date, and list the games found as a client.
+fn update_lobby_screen(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    mut status: Single<&mut Text, With<LobbyStatus>>,
+    mut game_list: Single<(Entity, &mut LobbyGameList)>,
+    font: Res<FontHandle>,
+    button: Res<ButtonNinePatch>,
+) {
+    let games = match lobby.state() {
+        LobbyState::Hosting { clients } => {
+            status.0 = format!(
+                "Hosting on port {}: {}/{MAX_PLAYERS} players",
+                lobby.game_port(),
+                clients.len() + 1
+            );
+            Vec::new()
+        }
+        LobbyState::Searching { games } if games.is_empty() => {
+            status.0 = "Searching for LAN games...".to_string();
+            Vec::new()
+        }
+        LobbyState::Searching { games } => {
+            status.0 = "Choose a game to join".to_string();
+            games
+                .iter()
+                .map(|game| {
+                    let label = format!(
+                        "{}: {} ({}/{})\n{}",
+                        game.host.ip(),
+                        level_id_name(&game.announcement.level),
+                        game.announcement.players,
+                        game.announcement.max_players,
+                        rules_summary(&game.announcement.ruleset)
+                    );
+                    (game.host, label)
+                })
+                .collect()
+        }
+        LobbyState::Joined { host } => {
+            status.0 = format!(
+                "Joined the game hosted by {}, waiting for it to start",
+                host.ip()
+            );
+            Vec::new()
+        }
+        LobbyState::Starting { .. } | LobbyState::Acknowledged { .. } => {
+            status.0 = "Starting the game...".to_string();
+            Vec::new()
+        }
+    };
+
+    let (game_list, listed_games) = &mut *game_list;
+    if listed_games.0 == games {
+        return;
+    }
+
+    commands.entity(*game_list).despawn_descendants();
+    for (host, label) in games.iter() {
+        let game_button = spawn_green_button_with_text(&mut commands, &font, &button, label);
+        commands
+            .entity(game_button)
+            .insert(LobbyButton::Join(*host))
+            .insert(Name::new(format!("Join {host} button")))
+            .set_parent(*game_list);
+    }
+    listed_games.0 = games;
+}
+
 fn spawn_green_button_with_text(
     commands: &mut Commands,
     font: &Res<FontHandle>,
@@ -19,7 +19,7 @@ impl Plugin for ZSortPlugin {
 /// The minimum Z value for transforms on the "Player" layer, i.e. players and bombs.
 pub const PLAYER_Z: f32 = 10.0;
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct ZSort(pub f32 /*the min Z value*/);
 
 /// Sort the players on the Z-axis based on their position on the Y-axis.